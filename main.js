@@ -15,17 +15,46 @@ const request = axios.create({
 });
 
 let room_id = "";
+// the invite code of a private room, from links like #<room>/<invite>
+let invite = "";
+// the seat matchmaking kept for us, handed over in the hello
+let reservation = null;
 const printable_chars = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
 
-function get_hash() {
-    if (!window.location.hash) {
-        room_id = "";
-        for (let i = 0; i < 5; i++) {
-            room_id += printable_chars[getRandomInt(printable_chars.length)];
+// queue for a table and resolve with its room id and our reservation
+function play_now() {
+    return new Promise((resolve, reject) => {
+        const ws = new WebSocket("ws://" + window.location.host + "/api/match");
+        ws.onmessage = ({data}) => {
+            const message = JSON.parse(data);
+            if (message.Matched) {
+                resolve(message.Matched);
+            } else if (message.Queued) {
+                console.log("waiting for a table with", message.Queued.waiting, "players");
+            }
+        };
+        ws.onclose = () => reject(new Error("left the queue"));
+    });
+}
+
+async function get_hash() {
+    if (window.location.hash == "#play") {
+        ({room: room_id, reservation} = await play_now());
+        window.history.replaceState(null, "", "#" + room_id);
+    } else if (!window.location.hash) {
+        try {
+            const { data } = await request.post("/rooms", {});
+            room_id = data.id;
+        } catch (e) {
+            console.log("failed to create a room: ", e);
+            room_id = "";
+            for (let i = 0; i < 5; i++) {
+                room_id += printable_chars[getRandomInt(printable_chars.length)];
+            }
         }
         window.history.replaceState(null, "", "#" + room_id);
     } else {
-        room_id = window.location.hash.slice(1);
+        [room_id, invite = ""] = window.location.hash.slice(1).split("/");
     }
     console.log("room_id: ", room_id);
 }
@@ -34,6 +63,8 @@ window.addEventListener("hashchange", get_hash);
 
 class Game {
     ws;
+    // sent away by the host, don't sit down again
+    kicked = false;
 
     constructor() {
         this.tryConnect();
@@ -41,12 +72,23 @@ class Game {
     }
 
     tryConnect() {
-        if (this.ws == undefined) {
+        if (this.ws == undefined && !this.kicked) {
+            const query = new URLSearchParams();
+            const session = localStorage.getItem("session");
+            if (session) {
+                query.set("token", session);
+            }
+            if (invite) {
+                query.set("invite", invite);
+            }
             let uri = "ws://" + window.location.host + "/api/ws/" + room_id;
+            if (query.size) {
+                uri += "?" + query;
+            }
             const ws = new WebSocket(uri)
             ws.onopen = () => {
                 this.ws = ws;
-                // this.sendTest();
+                this.sendHello();
                 this.sendReady();
             }
             ws.onmessage = ({data}) => {
@@ -132,25 +174,26 @@ class Game {
             }
 
         } else if (msg.Discard !== undefined) {
-            const {to, card: id} = msg.Discard;
-            if (to == my_turn || current_turn == my_turn) {return;}
+            const {seat, card: id} = msg.Discard;
+            // our own discards are shown when we play them
+            if (seat == my_turn) {return;}
             let card = new Card(id);
-            players[current_turn].out.push(card);
-            let container = document.querySelector("#" + players[current_turn].name + "-cards");
+            players[seat].out.push(card);
+            let container = document.querySelector("#" + players[seat].name + "-cards");
             append_out(container, card);
             render();
 
         } else if (msg.Pao !== undefined){
-            const {to, card: id} = msg.Pao;
-            if (to == my_turn || current_turn == my_turn) {return;}
+            const {seat, card: id} = msg.Pao;
+            if (seat == my_turn) {return;}
             let card = new Card(id);
-            let container = document.querySelector("#" + players[current_turn].name + "-pairing");
+            let container = document.querySelector("#" + players[seat].name + "-pairing");
             append_out(container, card, "抛");
         } else if (msg.Ding !== undefined){
-            const {to, card: id} = msg.Ding;
-            if (to == my_turn || current_turn == my_turn) {return;}
+            const {seat, card: id} = msg.Ding;
+            if (seat == my_turn) {return;}
             let card = new Card(id);
-            let container = document.querySelector("#" + players[current_turn].name + "-pairing");
+            let container = document.querySelector("#" + players[seat].name + "-pairing");
             append_out(container, card, "钉");
         } else if (msg.Hu !== undefined) {
             let result = document.querySelector("#result");
@@ -170,19 +213,41 @@ class Game {
                 result.className = "hide";
                 render_room();
             }, 3000);
+        } else if (msg.Robot !== undefined) {
+            const {id, name, difficulty} = msg.Robot;
+            show_robot(id, name, difficulty);
+        } else if (msg.Lobby !== undefined) {
+            console.log("lobby: ", msg.Lobby);
+        } else if (msg.Refused !== undefined) {
+            window.alert(msg.Refused.reason);
+        } else if (msg.Chat !== undefined) {
+            show_chat(msg.Chat.line);
+        } else if (msg.ChatHistory !== undefined) {
+            // the history is only sent to us, right after we took our seat
+            my_turn = msg.ChatHistory.to;
+            msg.ChatHistory.lines.forEach(show_chat);
+        } else if (msg.Kicked !== undefined) {
+            this.kicked = true;
+            window.alert("the host sent you away");
         } else {
             console.log("unrecognized message");
         }
     }
 
-    sendTest() {
-        this.ws.send(`{"Test": true}`);
+    sendHello() {
+        this.ws.send(JSON.stringify({Hello: {name: player_name(), user: player_id(), reservation}}));
+    }
+    sendChat(text) {
+        this.ws.send(JSON.stringify({Chat: {text}}));
+    }
+    sendEmote(emote) {
+        this.ws.send(JSON.stringify({Emote: emote}));
     }
     sendReady() {
         this.ws.send(`{"Ready": true}`);
     }
-    sendAddRobot() {
-        this.ws.send(`{"AddRobot": true}`);
+    sendAddRobot(difficulty) {
+        this.ws.send(`{"AddRobot": {"difficulty": "${difficulty}"}}`);
     }
     sendStart() {
         this.ws.send(`{"Start": true}`)
@@ -244,6 +309,45 @@ let room = document.querySelector('#room');
 
 start();
 
+// the name shown to the others, asked once and kept
+function player_name() {
+    let name = localStorage.getItem("name");
+    if (!name) {
+        name = window.prompt("Your name?", "player") || "player";
+        localStorage.setItem("name", name);
+    }
+    return name;
+}
+
+// tells this browser apart from others with the same name
+function player_id() {
+    let id = localStorage.getItem("user");
+    if (!id) {
+        id = "";
+        for (let i = 0; i < 16; i++) {
+            id += printable_chars[getRandomInt(printable_chars.length)];
+        }
+        localStorage.setItem("user", id);
+    }
+    return id;
+}
+
+// log in and keep the session for the next connections
+async function login(user, password) {
+    const { data } = await request.post("/sessions", {user, password});
+    localStorage.setItem("session", data.token);
+    localStorage.setItem("name", data.profile.name);
+    return data.profile;
+}
+
+async function register(user, password) {
+    await request.post("/accounts", {user, password});
+    return login(user, password);
+}
+
+window.login = login;
+window.register = register;
+
 function getRandomInt(max) {
     while (true) {
         let rand = Math.random();
@@ -282,7 +386,7 @@ function render_room()
 }
 
 async function start() {
-    get_hash();
+    await get_hash();
     render_room();
 
     game = new Game();
@@ -463,12 +567,13 @@ function create_player_slot(is_me) {
     let img = document.createElement("img");
     img.src = is_me ? "上大人/user.svg" : "上大人/add.svg";
     img.id = "icon";
-    if (!is_me) {
+    if (is_me) {
+        div.dataset.me = "";
+    } else {
         img.addEventListener('click', () => {
             console.log("hello", img.src)
             if (img.src.endsWith("add.svg")) {
-                img.src = "上大人/robot.svg";
-                game.sendAddRobot();
+                game.sendAddRobot(document.querySelector("#difficulty").value);
             }
         });
     }
@@ -477,6 +582,27 @@ function create_player_slot(is_me) {
     return div;
 }
 
+function show_chat({name, said}) {
+    const text = said.Text !== undefined ? said.Text : "[" + said.Emote + "]";
+    console.log(name + ": " + text);
+}
+
+function show_robot(id, name, difficulty) {
+    // our own seat comes first, then the ones after it in turn order
+    let slot = room.firstElementChild.children[(id - my_turn + 3) % 3];
+    if (slot === undefined || slot.dataset.me !== undefined) {
+        return;
+    }
+    slot.dataset.robot = name;
+    slot.firstElementChild.src = "上大人/robot.svg";
+    let label = slot.querySelector("div");
+    if (label === null) {
+        label = document.createElement("div");
+        slot.appendChild(label);
+    }
+    label.textContent = name + " (" + difficulty + ")";
+}
+
 function create_card(card, clickable, flag) {
     let div = document.createElement("div");
     div.id = "card-wrapper";
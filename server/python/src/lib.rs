@@ -0,0 +1,209 @@
+//! Python bindings of the rules, the simulator and the environment, so that
+//! notebooks play by the same rules as the server.
+//!
+//! Cards are the numbers `0..96`, a pairing is a `(card, size)` tuple with
+//! size 3 for a Ding and 4 for a Pao, and rules are the preset names of the
+//! `simulate` binary.
+//!
+//! Build and install it in the current environment with `maturin develop`
+//! from this directory.
+
+use std::{collections::HashMap, sync::Arc};
+
+use pyo3::{exceptions::PyValueError, prelude::*};
+use server::{
+    agent::Strategy,
+    card::{Card, Pairing},
+    env::{self, Step},
+    eval,
+    game::GameState,
+    record::Recorder,
+    rules::Rules,
+    simulate,
+};
+
+fn value_error(e: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+fn card(n: u8) -> PyResult<Card> {
+    if (n as usize) < GameState::TOTAL {
+        Ok(Card(n))
+    } else {
+        Err(value_error(format!("no card {n}")))
+    }
+}
+
+fn cards(hand: &[u8]) -> PyResult<Vec<Card>> {
+    hand.iter().map(|&n| card(n)).collect()
+}
+
+fn pairings(pairing: &[(u8, u8)]) -> PyResult<Vec<Pairing>> {
+    pairing
+        .iter()
+        .map(|&(n, size)| match size {
+            3 => Ok(Pairing::Triplet(card(n)?)),
+            4 => Ok(Pairing::Quadlet(card(n)?)),
+            _ => Err(value_error(format!(
+                "a pairing has 3 or 4 cards, got {size}"
+            ))),
+        })
+        .collect()
+}
+
+fn parse<T: std::str::FromStr>(s: &str) -> PyResult<T>
+where
+    T::Err: std::fmt::Display,
+{
+    s.parse().map_err(value_error)
+}
+
+/// turn what serde makes of `value` into python objects
+fn to_python(py: Python<'_>, value: &impl serde::Serialize) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).map_err(value_error)?;
+    let loads = py.import("json")?.getattr("loads")?;
+    Ok(loads.call1((json,))?.unbind())
+}
+
+/// Whether `hand`, with the cards of `pairing` set aside, wins with at least
+/// `min_score`.
+#[pyfunction]
+#[pyo3(signature = (hand, jing, pairing = vec![], min_score = eval::MIN_HU_SCORE))]
+fn is_hu(hand: Vec<u8>, jing: u8, pairing: Vec<(u8, u8)>, min_score: u8) -> PyResult<bool> {
+    let score = pairings(&pairing)?.iter().map(|p| p.score()).sum();
+    Ok(GameState::is_hu(
+        &cards(&hand)?,
+        score,
+        card(jing)?,
+        min_score,
+    ))
+}
+
+/// The melds, the eye and the score of a complete hand, `None` when the
+/// hand does not split into melds and an eye.
+#[pyfunction]
+#[pyo3(signature = (hand, jing, pairing = vec![]))]
+fn breakdown(
+    py: Python<'_>,
+    hand: Vec<u8>,
+    jing: u8,
+    pairing: Vec<(u8, u8)>,
+) -> PyResult<PyObject> {
+    let breakdown = eval::breakdown(&cards(&hand)?, &pairings(&pairing)?, card(jing)?);
+    to_python(py, &breakdown)
+}
+
+/// `(distance, score)` of the closest hu scoring at least `min_score`, or
+/// `None` when none can be reached.
+///
+/// `remaining` maps a kind to the cards of it that may still be drawn.
+#[pyfunction]
+#[pyo3(signature = (hand, jing, pairing = vec![], remaining = None, min_score = eval::MIN_HU_SCORE))]
+fn distance(
+    hand: Vec<u8>,
+    jing: u8,
+    pairing: Vec<(u8, u8)>,
+    remaining: Option<HashMap<u8, u8>>,
+    min_score: u8,
+) -> PyResult<Option<(u8, u8)>> {
+    let value = eval::evaluate(
+        &cards(&hand)?,
+        &pairings(&pairing)?,
+        card(jing)?,
+        remaining.as_ref(),
+        min_score,
+    );
+    Ok(value.map(|v| (v.distance, v.score)))
+}
+
+/// Robots playing each other, see the `simulate` binary.
+#[pyclass]
+struct Simulation {
+    inner: simulate::Simulation,
+    recorder: Option<Arc<Recorder>>,
+}
+
+#[pymethods]
+impl Simulation {
+    /// `seats` are strategy names such as `level1` or `random`, `record`
+    /// a JSONL file to write every decision to.
+    #[new]
+    #[pyo3(signature = (seats, rules = "standard", seed = None, record = None))]
+    fn new(
+        seats: [String; 3],
+        rules: &str,
+        seed: Option<u64>,
+        record: Option<&str>,
+    ) -> PyResult<Self> {
+        let seats = [parse(&seats[0])?, parse(&seats[1])?, parse(&seats[2])?];
+        let mut inner =
+            simulate::Simulation::new(seats, parse(rules)?, seed.unwrap_or_else(rand::random));
+        let recorder = match record {
+            Some(path) => Some(Arc::new(Recorder::create(path).map_err(value_error)?)),
+            None => None,
+        };
+        if let Some(recorder) = &recorder {
+            inner = inner.with_recorder(recorder.clone());
+        }
+        Ok(Self { inner, recorder })
+    }
+
+    /// Play `games` games and return the report as a dict.
+    #[pyo3(signature = (games, threads = 1))]
+    fn run(&self, py: Python<'_>, games: usize, threads: usize) -> PyResult<PyObject> {
+        let report = py
+            .allow_threads(|| self.inner.run(games, threads))
+            .map_err(value_error)?;
+        if let Some(recorder) = &self.recorder {
+            recorder.flush().map_err(value_error)?;
+        }
+        to_python(py, &report)
+    }
+}
+
+/// The learner plays seat 0 against two robots; `reset` and `step` return
+/// `(observation, reward, done, legal)`.
+#[pyclass(unsendable)]
+struct Env {
+    inner: env::Env,
+}
+
+fn step_tuple(step: Step) -> (Vec<f32>, f32, bool, Vec<bool>) {
+    (step.observation, step.reward, step.done, step.legal)
+}
+
+#[pymethods]
+impl Env {
+    #[new]
+    #[pyo3(signature = (opponents = ["level1".to_string(), "level1".to_string()], rules = "standard"))]
+    fn new(opponents: [String; 2], rules: &str) -> PyResult<Self> {
+        let opponents: [Strategy; 2] = [parse(&opponents[0])?, parse(&opponents[1])?];
+        let rules: Rules = parse(rules)?;
+        Ok(Self {
+            inner: env::Env::new(opponents, rules),
+        })
+    }
+
+    fn reset(&mut self, seed: u64) -> PyResult<(Vec<f32>, f32, bool, Vec<bool>)> {
+        self.inner.reset(seed).map(step_tuple).map_err(value_error)
+    }
+
+    fn step(&mut self, action: usize) -> PyResult<(Vec<f32>, f32, bool, Vec<bool>)> {
+        self.inner.step(action).map(step_tuple).map_err(value_error)
+    }
+}
+
+#[pymodule]
+fn shangdaren(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(is_hu, m)?)?;
+    m.add_function(wrap_pyfunction!(breakdown, m)?)?;
+    m.add_function(wrap_pyfunction!(distance, m)?)?;
+    m.add_class::<Simulation>()?;
+    m.add_class::<Env>()?;
+    m.add("ACTIONS", env::ACTIONS)?;
+    m.add("CLAIM", env::CLAIM)?;
+    m.add("PASS", env::PASS)?;
+    m.add("OBSERVATION_SIZE", env::OBSERVATION_SIZE)?;
+    m.add("MIN_HU_SCORE", eval::MIN_HU_SCORE)?;
+    Ok(())
+}
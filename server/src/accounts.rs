@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::room::random_string;
+
+/// A registered player, as others see them.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Profile {
+    /// what they log in with, unique and never changed
+    pub user: String,
+    /// what the table shows, the login unless they changed it
+    pub name: String,
+    pub created: u64,
+}
+
+/// What registering and logging in take.
+#[derive(Deserialize, Debug)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+/// A logged in player, the token goes in `Authorization: Bearer` or in the
+/// `token` of the websocket url.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Session {
+    pub token: String,
+    pub profile: Profile,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Account {
+    profile: Profile,
+    /// the argon2 hash in PHC format
+    password: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Login {
+    user: String,
+    expires: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Data {
+    accounts: HashMap<String, Account>,
+    sessions: HashMap<String, Login>,
+}
+
+/// The registered players and their sessions, kept in a JSON file written
+/// on every change, or only in memory.
+pub struct Accounts {
+    path: Option<PathBuf>,
+    data: Mutex<Data>,
+}
+
+/// Read the JSON in `path`, the default value when there is no file yet.
+pub(crate) fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("failed to parse {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// Replace `path` with `value`, a crash while writing leaves the old file
+/// in place.
+pub(crate) fn save_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec(value)?)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_secs()
+}
+
+impl Accounts {
+    pub const MAX_NAME: usize = 24;
+    const MIN_PASSWORD: usize = 8;
+    /// how long a session lasts, in seconds
+    const SESSION: u64 = 30 * 24 * 60 * 60;
+
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            data: Default::default(),
+        }
+    }
+
+    /// Keep the accounts in `path`, loading the ones already there.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let data = load_json(&path)?;
+        Ok(Self {
+            path: Some(path),
+            data: Mutex::new(data),
+        })
+    }
+
+    fn save(&self, data: &Data) -> Result<()> {
+        match &self.path {
+            Some(path) => save_json(path, data),
+            None => Ok(()),
+        }
+    }
+
+    fn check_name(name: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            bail!("empty name");
+        }
+        if name.chars().count() > Self::MAX_NAME {
+            bail!("names are at most {} characters", Self::MAX_NAME);
+        }
+        Ok(())
+    }
+
+    pub fn register(&self, credentials: &Credentials) -> Result<Profile> {
+        let user = credentials.user.trim();
+        Self::check_name(user)?;
+        if !user
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!("logins are letters, digits, _ and -");
+        }
+        if credentials.password.chars().count() < Self::MIN_PASSWORD {
+            bail!("passwords are at least {} characters", Self::MIN_PASSWORD);
+        }
+        let salt = SaltString::generate(&mut OsRng);
+        let password = Argon2::default()
+            .hash_password(credentials.password.as_bytes(), &salt)
+            .map_err(|e| anyhow!("failed to hash the password: {e}"))?
+            .to_string();
+        let profile = Profile {
+            user: user.to_string(),
+            name: user.to_string(),
+            created: now(),
+        };
+        let mut data = self.data.lock();
+        if data.accounts.contains_key(user) {
+            bail!("{user} is taken");
+        }
+        data.accounts.insert(
+            user.to_string(),
+            Account {
+                profile: profile.clone(),
+                password,
+            },
+        );
+        self.save(&data)?;
+        Ok(profile)
+    }
+
+    /// Open a session, `None` when the login or the password is wrong.
+    pub fn login(&self, credentials: &Credentials) -> Result<Option<Session>> {
+        let Some(account) = self
+            .data
+            .lock()
+            .accounts
+            .get(credentials.user.trim())
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        // verifying takes a while, don't hold the lock meanwhile
+        let hash = PasswordHash::new(&account.password)
+            .map_err(|e| anyhow!("bad hash for {}: {e}", account.profile.user))?;
+        if Argon2::default()
+            .verify_password(credentials.password.as_bytes(), &hash)
+            .is_err()
+        {
+            return Ok(None);
+        }
+        let token = random_string(32);
+        let mut data = self.data.lock();
+        let now = now();
+        data.sessions.retain(|_, login| login.expires > now);
+        data.sessions.insert(
+            token.clone(),
+            Login {
+                user: account.profile.user.clone(),
+                expires: now + Self::SESSION,
+            },
+        );
+        self.save(&data)?;
+        Ok(Some(Session {
+            token,
+            profile: account.profile,
+        }))
+    }
+
+    pub fn logout(&self, token: &str) -> Result<()> {
+        let mut data = self.data.lock();
+        if data.sessions.remove(token).is_some() {
+            self.save(&data)?;
+        }
+        Ok(())
+    }
+
+    /// the player logged in with `token`, if the session is still open
+    pub fn authenticate(&self, token: &str) -> Option<Profile> {
+        let data = self.data.lock();
+        let login = data.sessions.get(token)?;
+        if login.expires <= now() {
+            return None;
+        }
+        data.accounts.get(&login.user).map(|a| a.profile.clone())
+    }
+
+    /// Change the name shown at the table.
+    pub fn rename(&self, user: &str, name: &str) -> Result<Profile> {
+        let name = name.trim();
+        Self::check_name(name)?;
+        let mut data = self.data.lock();
+        let account = data
+            .accounts
+            .get_mut(user)
+            .with_context(|| format!("no account {user}"))?;
+        account.profile.name = name.to_string();
+        let profile = account.profile.clone();
+        self.save(&data)?;
+        Ok(profile)
+    }
+
+    pub fn profile(&self, user: &str) -> Option<Profile> {
+        self.data
+            .lock()
+            .accounts
+            .get(user)
+            .map(|a| a.profile.clone())
+    }
+}
+
+/// The file named by `SHANGDAREN_ACCOUNTS`, or memory when it is not set.
+pub fn configured() -> Result<Arc<Accounts>> {
+    Ok(Arc::new(match std::env::var_os("SHANGDAREN_ACCOUNTS") {
+        Some(path) => Accounts::open(path)?,
+        None => Accounts::in_memory(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(user: &str, password: &str) -> Credentials {
+        Credentials {
+            user: user.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_sessions() {
+        let path =
+            std::env::temp_dir().join(format!("shangdaren-accounts-{}.json", std::process::id()));
+        let accounts = Accounts::open(&path).unwrap();
+        let profile = accounts
+            .register(&credentials("alice", "hunter22"))
+            .unwrap();
+        assert_eq!(profile.name, "alice");
+        assert!(accounts
+            .register(&credentials("alice", "hunter22"))
+            .is_err());
+        assert!(accounts.register(&credentials("bob", "short")).is_err());
+        assert!(accounts.register(&credentials("a b", "hunter22")).is_err());
+
+        assert!(accounts
+            .login(&credentials("alice", "wrong password"))
+            .unwrap()
+            .is_none());
+        assert!(accounts
+            .login(&credentials("nobody", "hunter22"))
+            .unwrap()
+            .is_none());
+        let session = accounts
+            .login(&credentials("alice", "hunter22"))
+            .unwrap()
+            .unwrap();
+        accounts.rename("alice", "Alice").unwrap();
+        assert_eq!(accounts.authenticate(&session.token).unwrap().name, "Alice");
+        assert!(accounts.authenticate("forged").is_none());
+
+        // accounts and sessions outlive the server
+        let reopened = Accounts::open(&path).unwrap();
+        assert_eq!(reopened.authenticate(&session.token).unwrap().name, "Alice");
+        reopened.logout(&session.token).unwrap();
+        assert!(reopened.authenticate(&session.token).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,22 +1,89 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, str::FromStr};
 
-use log::{debug, info};
+use anyhow::bail;
+use log::{debug, info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     card::{Card, Pairing},
+    eval,
+    external::{self, Answer, ExternalBot},
     game::GameState,
+    opponent::Opponent,
+    rules::Rules,
+    weights::Weights,
 };
 
-#[derive(Debug)]
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug, Default)]
 pub enum Strategy {
     Random,
+    #[default]
     Level1,
+    /// Level1 claims, discarding whatever keeps the hand closest to hu
+    Lookahead,
+    /// the fixed play of the test hands, clients cannot ask for it
+    #[cfg_attr(not(test), serde(skip_deserializing))]
     Test,
+    /// a bot registered in the bots config, see `external::registered`
+    External(String),
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    /// `random`, `level1`, `lookahead`, `test` or `external:<bot>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(bot) = s.strip_prefix("external:") {
+            return Ok(Strategy::External(bot.to_string()));
+        }
+        match s.to_lowercase().as_str() {
+            "random" => Ok(Strategy::Random),
+            "level1" => Ok(Strategy::Level1),
+            "lookahead" => Ok(Strategy::Lookahead),
+            "test" => Ok(Strategy::Test),
+            _ => bail!("unknown strategy {s}"),
+        }
+    }
 }
 
-impl Default for Strategy {
-    fn default() -> Self {
-        Self::Level1
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::Random => write!(f, "random"),
+            Strategy::Level1 => write!(f, "level1"),
+            Strategy::Lookahead => write!(f, "lookahead"),
+            Strategy::Test => write!(f, "test"),
+            Strategy::External(bot) => write!(f, "external:{bot}"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    /// the strategy a robot of this difficulty plays when none is chosen
+    pub fn strategy(&self) -> Strategy {
+        match self {
+            Difficulty::Easy => Strategy::Random,
+            Difficulty::Normal => Strategy::Level1,
+            Difficulty::Hard => Strategy::Lookahead,
+        }
+    }
+
+    /// chance that a robot ignores its strategy and plays a random move
+    pub fn mistake_rate(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.3,
+            Difficulty::Normal => 0.1,
+            Difficulty::Hard => 0.0,
+        }
     }
 }
 
@@ -32,16 +99,89 @@ pub struct Agent {
     pub round: u8,
     pub turn: u8,
     pub is_robot: bool,
+    /// a robot keeping the seat of a human who left, until someone takes it
+    pub stand_in: bool,
+    /// the login of the player at the seat, whose stats the hands count for
+    pub account: Option<String>,
     pub ready: bool,
     pub strategy: Strategy,
+    pub external: Option<ExternalBot>,
+    /// what the external bot answered ahead of the next decision
+    answer: Option<Answer>,
+    pub difficulty: Difficulty,
+    pub name: String,
+    pub rules: Rules,
+    pub weights: Weights,
     pub id: u8,
+    rng: Option<StdRng>,
     prob: HashMap<u8, u8>,
     remaining: u8,
+    /// cards left in the wall when the table tells, guessed otherwise
+    pub wall: Option<u8>,
     pub jing: Card,
     ting: Option<Vec<u8>>,
     history: Vec<Action>,
+    /// keep the decisions in `decisions`, for self-play datasets
+    pub record: bool,
+    /// the recorded decisions, kept across hands until taken
+    pub decisions: Vec<Decision>,
+}
+
+/// Everything a robot knows when it makes a decision.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Observation {
+    pub id: u8,
+    pub hand: Vec<Card>,
+    pub out: Vec<Card>,
+    pub pairing: Vec<Pairing>,
+    pub right_out: Vec<Card>,
+    pub right_pairing: Vec<Pairing>,
+    pub left_out: Vec<Card>,
+    pub left_pairing: Vec<Pairing>,
+    pub jing: Card,
+    /// number of cards not seen yet, in the wall or in the neighbours' hands
+    pub unseen: u8,
+    /// number of cards left in the wall
+    #[serde(default)]
+    pub wall: u8,
+}
+
+impl Observation {
+    /// the cards that are neither in hand, nor discarded, nor claimed
+    pub fn count_unseen(&self) -> u8 {
+        let outs = self.out.len() + self.right_out.len() + self.left_out.len();
+        let claimed: usize = self
+            .pairing
+            .iter()
+            .chain(&self.right_pairing)
+            .chain(&self.left_pairing)
+            .map(|p| match p {
+                Pairing::Triplet(_) => 3,
+                Pairing::Quadlet(_) => 4,
+            })
+            .sum();
+        (96 - self.hand.len() - outs - claimed) as u8
+    }
 }
 
+/// A choice a robot can make.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Move {
+    Discard(Card),
+    Ding(bool),
+    Pao(bool),
+}
+
+/// A recorded decision point.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Decision {
+    pub observation: Observation,
+    pub legal: Vec<Move>,
+    pub chosen: Move,
+}
+
+// only read through `Debug` when `check_state` fails
+#[allow(dead_code)]
 #[derive(Debug)]
 enum Action {
     Draw(Card),
@@ -50,13 +190,13 @@ enum Action {
     Pao(Card),
 }
 
-fn divide_into_group(hand: &Vec<Card>) -> Vec<Vec<Card>> {
-    assert!(hand.len() > 0);
+fn divide_into_group(hand: &[Card]) -> Vec<Vec<Card>> {
+    assert!(!hand.is_empty());
     let mut group = vec![];
     let mut tmp = vec![];
     for i in 0..96 {
         if i % 12 == 0 {
-            if tmp.len() != 0 {
+            if !tmp.is_empty() {
                 group.push(tmp.clone());
             }
             tmp.clear();
@@ -65,7 +205,7 @@ fn divide_into_group(hand: &Vec<Card>) -> Vec<Vec<Card>> {
             tmp.push(Card(i));
         }
     }
-    if tmp.len() != 0 {
+    if !tmp.is_empty() {
         group.push(tmp.clone());
     }
     group
@@ -76,14 +216,13 @@ impl Agent {
         self.history.push(Action::Draw(card));
         self.hand.push(card);
     }
+    /// the cards in hand and in the pairings, the replacement drawn for a
+    /// Pao stands in for its fourth card
+    pub fn card_count(&self) -> usize {
+        self.hand.len() + 3 * self.pairing.len()
+    }
     pub fn check_state(&self, expect: usize) {
-        let mut card_size = self.hand.len();
-        for pair in &self.pairing {
-            card_size += match pair {
-                Pairing::Triplet(_) => 3,
-                Pairing::Quadlet(_) => 3,
-            };
-        }
+        let card_size = self.card_count();
         assert!(
             card_size == expect,
             "the total card should be {}, got {}, history: {:?}",
@@ -105,10 +244,112 @@ impl Agent {
         self.round = 0;
         self.prob.clear();
         self.remaining = 0;
+        self.wall = None;
     }
-    pub fn discard_card(&mut self) -> Card {
-        let index = match self.strategy {
-            Strategy::Random => rand::random::<usize>() % self.hand.len(),
+    /// Set the strategy, launching the bot of an external one. A bot that
+    /// cannot be launched is replaced by the default strategy.
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.external = None;
+        if let Strategy::External(bot) = &strategy {
+            match external::registered(bot).and_then(|config| ExternalBot::spawn(&config)) {
+                Ok(bot) => self.external = Some(bot),
+                Err(e) => warn!("robot {} falls back: {e:#}", self.id),
+            }
+        }
+        self.strategy = strategy;
+    }
+
+    pub fn observe(&self) -> Observation {
+        let mut observation = Observation {
+            id: self.id,
+            hand: self.hand.clone(),
+            out: self.out.clone(),
+            pairing: self.pairing.clone(),
+            right_out: self.player_right_out.clone(),
+            right_pairing: self.player_right_pairing.clone(),
+            left_out: self.player_left_out.clone(),
+            left_pairing: self.player_left_pairing.clone(),
+            jing: self.jing,
+            unseen: 0,
+            wall: 0,
+        };
+        // counted afresh since `remaining` is only updated after a discard
+        // or a claim
+        observation.unseen = observation.count_unseen();
+        observation.wall = self.wall.unwrap_or_else(|| {
+            let neighbours = [&self.player_right_pairing, &self.player_left_pairing]
+                .iter()
+                .map(|pairing| Opponent { out: &[], pairing }.hand_size())
+                .sum::<u8>();
+            observation.unseen.saturating_sub(neighbours)
+        });
+        observation
+    }
+
+    fn record_decision(&mut self, legal: Vec<Move>, chosen: Move) {
+        if self.record {
+            let observation = self.observe();
+            self.decisions.push(Decision {
+                observation,
+                legal,
+                chosen,
+            });
+        }
+    }
+
+    /// Make the robot's random choices reproducible.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Some(StdRng::seed_from_u64(seed));
+    }
+
+    fn rng(&mut self) -> &mut StdRng {
+        self.rng.get_or_insert_with(StdRng::from_entropy)
+    }
+
+    /// the strategy for the next decision, a mistake is played as `Random`
+    fn next_strategy(&mut self) -> Strategy {
+        let mistake_rate = self.difficulty.mistake_rate();
+        let thinks = matches!(self.strategy, Strategy::Level1 | Strategy::Lookahead);
+        if thinks && self.rng().gen::<f32>() < mistake_rate {
+            Strategy::Random
+        } else {
+            self.strategy.clone()
+        }
+    }
+
+    /// Whether the external bot has to be asked before the next decision.
+    pub fn needs_answer(&self) -> bool {
+        matches!(self.strategy, Strategy::External(_))
+            && self.answer.is_none()
+            && self.external.as_ref().is_some_and(ExternalBot::is_alive)
+    }
+
+    /// Lend the external bot out with what it sees, to ask it without
+    /// holding the game. `answered` gives it back.
+    pub fn lend_bot(&mut self) -> Option<(ExternalBot, Observation)> {
+        let observation = self.observe();
+        self.external.take().map(|bot| (bot, observation))
+    }
+
+    pub fn answered(&mut self, bot: ExternalBot, answer: Answer) {
+        self.external = Some(bot);
+        self.answer = Some(answer);
+    }
+
+    /// the strategy an external bot falls back to when it misbehaves
+    fn fallback(&self) -> Strategy {
+        self.external
+            .as_ref()
+            .map(|bot| bot.fallback.clone())
+            .unwrap_or_default()
+    }
+
+    fn discard_index(&mut self, strategy: &Strategy) -> usize {
+        match strategy {
+            Strategy::Random => {
+                let len = self.hand.len();
+                self.rng().gen_range(0..len)
+            }
             Strategy::Level1 => {
                 if self.ting.is_some() {
                     self.ting_card()
@@ -116,12 +357,61 @@ impl Agent {
                     self.choose_discard_card()
                 }
             }
+            Strategy::Lookahead => self.closest_discard(),
             Strategy::Test => 0,
-        };
+            Strategy::External(_) => {
+                let card = match self.answer.take() {
+                    Some(Answer::Discard(card)) => Some(card),
+                    Some(_) => None,
+                    None => {
+                        let observation = self.observe();
+                        self.external
+                            .as_mut()
+                            .and_then(|bot| bot.discard(&observation))
+                    }
+                };
+                let index = card.and_then(|card| match self.hand.iter().position(|&c| c == card) {
+                    Some(index) => Some(index),
+                    None => self
+                        .external
+                        .as_mut()
+                        .and_then(|bot| bot.fail(&format!("{card:?} is not in hand"))),
+                });
+                index.unwrap_or_else(|| self.discard_index(&self.fallback()))
+            }
+        }
+    }
+
+    fn accept_claim(&mut self, strategy: &Strategy, claim: Pairing) -> bool {
+        match strategy {
+            Strategy::Random => self.rng().gen_bool(0.5),
+            Strategy::Level1 | Strategy::Lookahead => self.claim_helps(claim),
+            Strategy::Test => matches!(claim, Pairing::Triplet(_)),
+            Strategy::External(_) => {
+                let confirm = match self.answer.take() {
+                    Some(Answer::Claim(confirm)) => Some(confirm),
+                    Some(_) => None,
+                    None => {
+                        let observation = self.observe();
+                        self.external
+                            .as_mut()
+                            .and_then(|bot| bot.claim(claim, &observation))
+                    }
+                };
+                confirm.unwrap_or_else(|| self.accept_claim(&self.fallback(), claim))
+            }
+        }
+    }
+
+    pub fn discard_card(&mut self) -> Card {
+        let strategy = self.next_strategy();
+        let index = self.discard_index(&strategy);
         let card = *self
             .hand
             .get(index)
-            .expect(&format!("discard index {}", index));
+            .unwrap_or_else(|| panic!("discard index {}", index));
+        let legal = self.hand.iter().map(|&c| Move::Discard(c)).collect();
+        self.record_decision(legal, Move::Discard(card));
         info!("robot {} discard {:?}", self.turn, card);
         self.out.push(card);
 
@@ -135,6 +425,43 @@ impl Agent {
         card
     }
 
+    /// the discard leaving the hand closest to hu, then with the best
+    /// score, then the least dangerous; Level1's choice when none can win
+    fn closest_discard(&mut self) -> usize {
+        self.update_probability();
+        let min_score = self.rules.min_hu_score;
+        let mut best: Option<(eval::HandValue, f32, usize)> = None;
+        let mut hand = self.hand.clone();
+        for i in 0..hand.len() {
+            if self.hand[..i].iter().any(|c| c.is_same_kind(&self.hand[i])) {
+                continue;
+            }
+            hand.swap(0, i);
+            let value = eval::evaluate(
+                &hand[1..],
+                &self.pairing,
+                self.jing,
+                Some(&self.prob),
+                min_score,
+            );
+            hand.swap(0, i);
+            let Some(value) = value else {
+                continue;
+            };
+            let danger = self.danger(&self.hand[i]);
+            let better = best.is_none_or(|(b, d, _)| {
+                value.is_better_than(&b) || (!b.is_better_than(&value) && danger < d)
+            });
+            if better {
+                best = Some((value, danger, i));
+            }
+        }
+        match best {
+            Some((_, _, index)) => index,
+            None => self.discard_index(&Strategy::Level1),
+        }
+    }
+
     fn ting_card(&mut self) -> usize {
         let mut hand = self.hand.clone();
         let (mut best_score, mut best_index) = (0, 0);
@@ -165,34 +492,44 @@ impl Agent {
 
     fn choose_discard_card(&self) -> usize {
         let groups = divide_into_group(&self.hand);
+        let cards: Vec<Card> = groups
+            .iter()
+            .map(|group| self.select_worst_one_from_group(group))
+            .collect();
         let scores: Vec<f32> = groups
             .iter()
-            .map(|group| self.form_ke(group) + self.form_shun(group))
+            .zip(cards.iter())
+            .map(|(group, card)| {
+                self.form_ke(group)
+                    + self.form_shun(group)
+                    + self.weights.danger * self.danger(card)
+            })
             .collect();
         let (_, ind) = scores
             .iter()
             .enumerate()
-            .map(|(ind, value)| ((*value * 1000.0) as usize, ind))
+            .map(|(ind, value)| ((*value * self.weights.quantization) as usize, ind))
             .min()
-            .expect(&format!(
-                "cannot find min value in scores: {scores:?}, groups {groups:?}"
-            ));
+            .unwrap_or_else(|| {
+                panic!("cannot find min value in scores: {scores:?}, groups {groups:?}")
+            });
 
         // debug!(
         //     "[choose discard_card] groups: {:?}, scores: {:?}, choose ind: {ind}",
         //     groups, scores
         // );
-        let card = self.select_worst_one_from_group(&groups[ind]);
-        self.hand.iter().position(|&c| c == card).unwrap()
+        self.hand.iter().position(|&c| c == cards[ind]).unwrap()
     }
 
-    fn select_worst_one_from_group(&self, group: &Vec<Card>) -> Card {
-        let mut group = group.clone();
+    fn select_worst_one_from_group(&self, group: &[Card]) -> Card {
+        let mut group = group.to_vec();
         let mut scores = vec![];
         for i in 0..group.len() {
             group.swap(0, i);
 
-            let score = self.form_ke(&group[1..]) + self.form_shun(&group[1..]);
+            let score = self.form_ke(&group[1..])
+                + self.form_shun(&group[1..])
+                + self.weights.danger * self.danger(&group[0]);
             scores.push(score);
 
             group.swap(0, i);
@@ -200,7 +537,7 @@ impl Agent {
         let (_, ind) = scores
             .iter()
             .enumerate()
-            .map(|(ind, value)| ((*value * 1000.0) as usize, ind))
+            .map(|(ind, value)| ((*value * self.weights.quantization) as usize, ind))
             .min()
             .unwrap();
 
@@ -208,11 +545,9 @@ impl Agent {
     }
 
     pub fn pao_card(&mut self, card: Card) -> bool {
-        let res = match self.strategy {
-            Strategy::Random => rand::random::<u8>() % 2 == 1,
-            Strategy::Level1 => true,
-            Strategy::Test => return false,
-        };
+        let strategy = self.next_strategy();
+        let res = self.accept_claim(&strategy, Pairing::Quadlet(card));
+        self.record_decision(vec![Move::Pao(true), Move::Pao(false)], Move::Pao(res));
         if res {
             self.pairing.push(Pairing::Quadlet(card));
             let mut index = vec![];
@@ -226,8 +561,8 @@ impl Agent {
             debug!("[pao_card] index: {:?}", index);
             assert!(index.len() == 3);
             self.history.push(Action::Pao(card));
-            for i in 0..3 {
-                self.hand.remove(index[i] - i);
+            for (i, ind) in index.iter().enumerate() {
+                self.hand.remove(ind - i);
             }
             if let Some(c) = self.player_right_out.last() {
                 if c.is_same_kind(&card) {
@@ -245,10 +580,9 @@ impl Agent {
         res
     }
     pub fn ding_card(&mut self, card: Card) -> bool {
-        let res = match self.strategy {
-            Strategy::Random => rand::random::<u8>() % 2 == 1,
-            _ => true,
-        };
+        let strategy = self.next_strategy();
+        let res = self.accept_claim(&strategy, Pairing::Triplet(card));
+        self.record_decision(vec![Move::Ding(true), Move::Ding(false)], Move::Ding(res));
         if res {
             self.pairing.push(Pairing::Triplet(card));
             let mut index = vec![];
@@ -262,8 +596,8 @@ impl Agent {
             debug!("[ding_card] index: {:?}", index);
             assert_eq!(index.len(), 2);
             self.history.push(Action::Ding(card));
-            for i in 0..2 {
-                self.hand.remove(index[i] - i);
+            for (i, ind) in index.iter().enumerate() {
+                self.hand.remove(ind - i);
             }
             if let Some(c) = self.player_right_out.last() {
                 if c.is_same_kind(&card) {
@@ -281,28 +615,72 @@ impl Agent {
         res
     }
 
+    /// the risk that discarding `card` feeds a neighbour a Ding or a Pao
+    fn danger(&self, card: &Card) -> f32 {
+        let kind = card.0 / 4;
+        let unseen = self.prob.get(&kind).copied().unwrap_or(0);
+        [
+            Opponent {
+                out: &self.player_right_out,
+                pairing: &self.player_right_pairing,
+            },
+            Opponent {
+                out: &self.player_left_out,
+                pairing: &self.player_left_pairing,
+            },
+        ]
+        .iter()
+        .map(|o| o.danger(kind, self.jing, unseen, self.remaining))
+        .sum()
+    }
+
+    /// Claim only if it brings the hand closer to hu, or keeps the distance
+    /// and raises the score. A Pao also earns a draw, so it wins ties.
+    fn claim_helps(&mut self, claim: Pairing) -> bool {
+        self.update_probability();
+        let min_score = self.rules.min_hu_score;
+        let without = eval::evaluate(
+            &self.hand,
+            &self.pairing,
+            self.jing,
+            Some(&self.prob),
+            min_score,
+        );
+        let with = eval::evaluate_claim(
+            &self.hand,
+            &self.pairing,
+            self.jing,
+            Some(&self.prob),
+            min_score,
+            claim,
+        );
+        debug!("[claim_helps] claim: {claim:?}, without: {without:?}, with: {with:?}");
+        match (with, without) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(with), Some(without)) => match claim {
+                Pairing::Triplet(_) => with.is_better_than(&without),
+                Pairing::Quadlet(_) => !without.is_better_than(&with),
+            },
+        }
+    }
+
     fn is_ting(&self, hand: &[Card]) -> Option<Vec<u8>> {
         let mut ting_card = vec![];
         let mut hand = hand.to_vec();
-        let mut score = 0;
-        for p in &self.pairing {
-            match p {
-                Pairing::Triplet(_) => score += 2,
-                Pairing::Quadlet(_) => score += 6,
-            }
-        }
+        let score = self.pairing.iter().map(|p| p.score()).sum();
         for i in 0..24 {
             if self.prob[&i] == 0 {
                 continue;
             }
             let c = Card(i * 4);
             hand.push(c);
-            if GameState::is_hu(&hand, score, self.jing) {
+            if GameState::is_hu(&hand, score, self.jing, self.rules.min_hu_score) {
                 ting_card.push(i);
             }
             hand.pop();
         }
-        if ting_card.len() == 0 {
+        if ting_card.is_empty() {
             return None;
         }
         Some(ting_card)
@@ -324,7 +702,7 @@ impl Agent {
                 .entry(c.0 / 4)
                 .or_insert(4)
                 .checked_sub(1)
-                .expect(&format!("cannot perform update on card {:?}", c));
+                .unwrap_or_else(|| panic!("cannot perform update on card {:?}", c));
         }
         // let mut sort_map: Vec<_> = mmap
         //     .iter()
@@ -362,24 +740,20 @@ impl Agent {
                         .entry(c.0 / 4)
                         .or_insert(4)
                         .checked_sub(3)
-                        .expect(&format!("cannot perform update on card {c:?}"))
+                        .unwrap_or_else(|| panic!("cannot perform update on card {c:?}"))
                 }
                 Pairing::Quadlet(c) => {
                     *mmap.entry(c.0 / 4).or_insert(4) = mmap
                         .entry(c.0 / 4)
                         .or_insert(4)
                         .checked_sub(4)
-                        .expect(&format!("cannot perform update on card {c:?}"))
+                        .unwrap_or_else(|| panic!("cannot perform update on card {c:?}"))
                 }
             };
         }
-        self.remaining = mmap.values().fold(0, |acc, x| acc + x);
+        self.remaining = mmap.values().sum::<u8>();
 
-        let mut sort_map: Vec<_> = mmap
-            .iter()
-            .map(|(key, value)| (key, value))
-            .filter(|(_, value)| **value != 0)
-            .collect();
+        let mut sort_map: Vec<_> = mmap.iter().filter(|(_, value)| **value != 0).collect();
         sort_map.sort();
 
         self.prob = mmap
@@ -425,13 +799,13 @@ impl Agent {
         let number = self
             .prob
             .get(&card_type)
-            .expect(&format!("cannot find the prob of type {card_type}"));
+            .unwrap_or_else(|| panic!("cannot find the prob of type {card_type}"));
         if *number == 0 {
             return 0.0;
         }
         let mut acc = 0.0;
         for i in 0..(1 << 6) {
-            if i & 1 != 1 || i & (1 << 3) != 1 {
+            if i & 1 != 1 || i & (1 << 3) == 0 {
                 continue;
             }
             let mut p = 1.0;
@@ -458,7 +832,7 @@ impl Agent {
     }
 
     fn form_shun(&self, group: &[Card]) -> f32 {
-        if group.len() == 0 {
+        if group.is_empty() {
             return 0.0;
         }
         let mut mmap: HashMap<u8, u8> = HashMap::new();
@@ -472,7 +846,7 @@ impl Agent {
             minus_entry(&mut mmap, j, 1);
             minus_entry(&mut mmap, k, 1);
         }
-        if mmap.len() == 0 {
+        if mmap.is_empty() {
             return f32::MAX;
         }
         let mut need_card = vec![];
@@ -482,25 +856,26 @@ impl Agent {
             }
         }
         // debug!("[form_shun], group: {group:?}, mmap: {mmap:?}, need_card: {need_card:?}");
+        let (near, far) = (self.weights.near_draws, self.weights.far_draws);
         let mut res = if need_card.len() == 1 {
-            self.get_prob_of(need_card[0], 3)
+            self.get_prob_of(need_card[0], near)
         } else {
             let (c1, c2) = (need_card[0], need_card[1]);
-            let p1 = self.get_prob_of(c1, 3) * self.get_prob_of(c2, 6);
-            let p2 = self.get_prob_of(c2, 3) * self.get_prob_of(c1, 6);
+            let p1 = self.get_prob_of(c1, near) * self.get_prob_of(c2, far);
+            let p2 = self.get_prob_of(c2, near) * self.get_prob_of(c1, far);
             // debug!("    p1: {p1}, p2: {p2}");
             p1 + p2
         };
         // debug!("    res: {res}");
         if cat == 0 || self.jing.0 / 12 == cat {
-            res *= 2.0;
+            res *= self.weights.valuable_category;
         }
 
         res
     }
 
     fn form_ke(&self, group: &[Card]) -> f32 {
-        if group.len() == 0 {
+        if group.is_empty() {
             return 0.0;
         }
         let mut mmap: HashMap<u8, u8> = HashMap::new();
@@ -516,34 +891,40 @@ impl Agent {
             }
         }
 
-        if mmap.len() == 0 {
+        if mmap.is_empty() {
             return f32::MAX;
         }
         let mut res = 0.0;
-        for (key, value) in &mmap {
-            assert!(*value < 3);
-            assert!(*value > 0);
+        // walk the kinds in order, iterating the map would make the result
+        // depend on its random order
+        for key in [i, j, k] {
+            let Some(&value) = mmap.get(&key) else {
+                continue;
+            };
+            assert!(value < 3);
+            assert!(value > 0);
             let cnt = 3 - value;
-            let number = *value;
+            let number = value;
             if cnt == 1 {
                 // draw prob
-                let p1 = self.get_prob_of(*key, 3);
+                let p1 = self.get_prob_of(key, self.weights.near_draws);
                 // peng prob
-                let p2 = number as f32 / (self.remaining + 19 * 2) as f32 * 2.0;
+                let p2 = number as f32 / (self.remaining as f32 + self.weights.claim_pool) * 2.0;
                 res = p1 + p2;
             } else if cnt == 2 {
                 // draw prob
-                let p1 = self.get_same_card_prob_of(*key);
+                let p1 = self.get_same_card_prob_of(key);
                 // 1 draw 1 peng prob
-                let p_prob = number as f32 / (self.remaining - 3 + 19 * 2) as f32 * 2.0;
-                let p2 = self.get_prob_of(*key, 3) * p_prob;
+                let p_prob =
+                    number as f32 / (self.remaining as f32 - 3.0 + self.weights.claim_pool) * 2.0;
+                let p2 = self.get_prob_of(key, self.weights.near_draws) * p_prob;
                 res = p1 + p2;
             } else {
                 unreachable!()
             }
         }
         if cat == 0 || self.jing.0 / 12 == cat {
-            res *= 2.0;
+            res *= self.weights.valuable_category;
         }
         res
     }
@@ -554,3 +935,45 @@ fn minus_entry(cnt: &mut HashMap<u8, u8>, key: u8, val: u8) {
         cnt.remove(&key);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a Level1 robot that never plays a random move
+    fn level1(hand: &[u8], jing: u8) -> Agent {
+        let mut agent = Agent {
+            is_robot: true,
+            difficulty: Difficulty::Hard,
+            hand: hand.iter().map(|&n| Card(n)).collect(),
+            jing: Card(jing),
+            ..Default::default()
+        };
+        agent.update_probability();
+        agent
+    }
+
+    #[test]
+    fn accepts_a_helpful_claim() {
+        // five sequences, a pair of kind 12 and two lone kinds: a Ding of 12
+        // leaves a single card to wait on
+        let hand = [
+            0, 4, 8, 12, 16, 20, 24, 28, 32, 36, 40, 44, 1, 5, 9, 48, 49, 72, 92,
+        ];
+        let mut agent = level1(&hand, 0);
+        assert!(agent.ding_card(Card(50)));
+        assert!(matches!(agent.pairing[..], [Pairing::Triplet(Card(50))]));
+        assert_eq!(agent.hand.len(), 17);
+    }
+
+    #[test]
+    fn lookahead_drops_a_lone_card() {
+        // four sequences, a triplet, a pair and two strays
+        let hand = [
+            0, 4, 8, 12, 16, 20, 24, 28, 32, 36, 40, 44, 48, 49, 50, 52, 53, 72, 92,
+        ];
+        let mut agent = level1(&hand, 95);
+        let index = agent.discard_index(&Strategy::Lookahead);
+        assert!([72, 92].contains(&agent.hand[index].0));
+    }
+}
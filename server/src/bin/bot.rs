@@ -0,0 +1,73 @@
+use anyhow::Result;
+use clap::Parser;
+use log::info;
+use server::{
+    agent::{Difficulty, Strategy},
+    client::{Bot, Client},
+    game::{ClientMessage, ServerMessage},
+};
+
+/// Join a room through the public WebSocket API and play a seat.
+#[derive(Parser)]
+struct Args {
+    /// the room to join
+    room: String,
+    #[arg(long, default_value = "ws://127.0.0.1:3131")]
+    server: String,
+    /// random, level1 or external:<bot>
+    #[arg(long, default_value = "level1")]
+    strategy: Strategy,
+    /// fill the room with this many server robots and start the game
+    #[arg(long, default_value_t = 0)]
+    robots: u8,
+    /// the name shown to the others at the table
+    #[arg(long, default_value = "bot")]
+    name: String,
+    /// leave after this many hands, play forever by default
+    #[arg(long)]
+    hands: Option<usize>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let args = Args::parse();
+    let url = format!("{}/api/ws/{}", args.server, args.room);
+    let mut client = Client::join(&url, &args.name).await?;
+    let mut bot = Bot::new(args.strategy);
+    client.send(ClientMessage::Ready(true)).await?;
+    for _ in 0..args.robots {
+        client
+            .send(ClientMessage::AddRobot {
+                strategy: None,
+                difficulty: Difficulty::default(),
+                name: None,
+            })
+            .await?;
+    }
+    if args.robots > 0 {
+        client.send(ClientMessage::Start(true)).await?;
+    }
+
+    let mut hands = 0;
+    while let Some(msg) = client.recv().await? {
+        if let ServerMessage::Closed { .. } = msg {
+            info!("the room was closed");
+            break;
+        }
+        for answer in bot.handle(&msg) {
+            client.send(answer).await?;
+        }
+        if let ServerMessage::Hu { .. } | ServerMessage::End { .. } = msg {
+            hands += 1;
+            info!("hand {hands} finished: {msg:?}");
+            if args.hands.is_some_and(|n| hands >= n) {
+                break;
+            }
+            if args.robots > 0 {
+                client.send(ClientMessage::Start(true)).await?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use clap::Parser;
+use server::{
+    card::Card,
+    replay::{Position, Replay},
+};
+
+/// Step through a recorded hand, or check that it was played by the rules.
+#[derive(Parser)]
+struct Args {
+    /// the replay file
+    file: PathBuf,
+    /// check every event and the result, then exit
+    #[arg(long)]
+    verify: bool,
+    /// the step to show first
+    #[arg(long, default_value_t = 0)]
+    step: usize,
+}
+
+fn cards(cards: &[Card]) -> String {
+    let mut cards = cards.to_vec();
+    cards.sort();
+    cards
+        .iter()
+        .map(|c| c.0.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn show(replay: &Replay, position: &Position) {
+    println!(
+        "step {}/{}, jing {}, wall {}",
+        position.step,
+        replay.events.len(),
+        replay.jing.0,
+        position.wall.len()
+    );
+    if let Some(event) = position.step.checked_sub(1).map(|i| replay.events[i]) {
+        println!("last: {event:?}");
+    }
+    for (i, seat) in replay.seats.iter().enumerate() {
+        let marker = if position.turn() == Some(i as u8) {
+            '>'
+        } else {
+            ' '
+        };
+        let strategy = seat
+            .strategy
+            .as_ref()
+            .map_or("human".to_string(), |s| s.to_string());
+        println!(
+            "{marker} seat {i} {} ({strategy}): {} | pairing {:?} | out {}",
+            seat.name,
+            cards(&position.hands[i]),
+            position.pairings[i],
+            cards(&position.outs[i])
+        );
+    }
+}
+
+fn browse(replay: &Replay, step: usize) -> Result<()> {
+    let mut position = replay.position(step)?;
+    let mut line = String::new();
+    loop {
+        show(replay, &position);
+        print!("[n]ext, [p]revious, a step or [q]uit> ");
+        io::stdout().flush()?;
+        line.clear();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let step = match line.trim() {
+            "" | "n" => position.step + 1,
+            "p" => position.step.saturating_sub(1),
+            "q" => return Ok(()),
+            n => match n.parse() {
+                Ok(n) => n,
+                Err(_) => {
+                    println!("unknown command {n}");
+                    continue;
+                }
+            },
+        };
+        match replay.position(step) {
+            Ok(p) => position = p,
+            Err(e) => println!("{e:#}"),
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let args = Args::parse();
+    let replay = Replay::load(&args.file)?;
+    if args.verify {
+        replay.verify()?;
+        println!("ok, {} events, {:?}", replay.events.len(), replay.result());
+        return Ok(());
+    }
+    browse(&replay, args.step)
+}
@@ -0,0 +1,202 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use server::{
+    agent::Strategy,
+    ladder::{Ladder, Schedule},
+    record::Recorder,
+    rules::Rules,
+    simulate::Simulation,
+    tune::Tuner,
+};
+
+/// Play robots against each other and report how each strategy does.
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    line_up: LineUp,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Rate a pool of strategies playing each other
+    Ladder(LadderArgs),
+    /// Search the Level1 weights winning the most against two opponents
+    Tune(TuneArgs),
+}
+
+#[derive(Args)]
+struct Common {
+    /// games per line-up
+    #[arg(long, default_value_t = 1000)]
+    games: usize,
+    /// a random seed by default, the report shows the one used
+    #[arg(long)]
+    seed: Option<u64>,
+    /// standard, casual or no-claims
+    #[arg(long, default_value = "standard")]
+    rules: Rules,
+    /// worker threads, all cores by default
+    #[arg(long)]
+    threads: Option<usize>,
+}
+
+#[derive(Args)]
+struct LineUp {
+    /// the strategies of the three seats, e.g. level1,random,random
+    #[arg(long, value_delimiter = ',', default_value = "level1,random,random")]
+    seats: Vec<Strategy>,
+    #[command(flatten)]
+    common: Common,
+    /// print the report as JSON
+    #[arg(long)]
+    json: bool,
+    /// write every decision of the robots to this JSONL file
+    #[arg(long)]
+    record: Option<String>,
+}
+
+#[derive(Args)]
+struct LadderArgs {
+    /// the strategies to rate, at least three
+    #[arg(long, value_delimiter = ',', required = true)]
+    strategies: Vec<Strategy>,
+    /// round-robin or swiss
+    #[arg(long, default_value = "round-robin")]
+    schedule: Schedule,
+    #[arg(long, default_value_t = 10)]
+    rounds: usize,
+    #[command(flatten)]
+    common: Common,
+    /// write the leaderboard there, as CSV if the name ends with `.csv` and
+    /// as JSON otherwise
+    #[arg(long)]
+    out: Option<String>,
+}
+
+#[derive(Args)]
+struct TuneArgs {
+    /// the two strategies to beat
+    #[arg(long, value_delimiter = ',', default_value = "level1,level1")]
+    opponents: Vec<Strategy>,
+    /// candidates to try
+    #[arg(long, default_value_t = 50)]
+    iterations: usize,
+    #[command(flatten)]
+    common: Common,
+    /// where to write the best weights, load them in robots by pointing
+    /// `SHANGDAREN_WEIGHTS` at the file
+    #[arg(long, default_value = "weights.json")]
+    out: String,
+}
+
+impl Common {
+    fn seed(&self) -> u64 {
+        self.seed.unwrap_or_else(rand::random)
+    }
+
+    fn threads(&self) -> usize {
+        self.threads.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+    }
+}
+
+fn simulate(args: LineUp) -> Result<()> {
+    let seats: [Strategy; 3] = args
+        .seats
+        .try_into()
+        .map_err(|s: Vec<Strategy>| anyhow!("expect 3 seats, got {}", s.len()))?;
+    let common = args.common;
+    let mut simulation = Simulation::new(seats, common.rules, common.seed());
+    let recorder = match &args.record {
+        Some(path) => Some(Arc::new(Recorder::create(path)?)),
+        None => None,
+    };
+    if let Some(recorder) = &recorder {
+        simulation = simulation.with_recorder(recorder.clone());
+    }
+    let report = simulation.run(common.games, common.threads())?;
+    if let Some(recorder) = recorder {
+        recorder.flush()?;
+    }
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{report}");
+    }
+    Ok(())
+}
+
+fn ladder(args: LadderArgs) -> Result<()> {
+    let common = args.common;
+    let seed = common.seed();
+    let mut ladder = Ladder::new(
+        args.strategies,
+        args.schedule,
+        common.rules,
+        seed,
+        common.games,
+        common.threads(),
+    )?;
+    ladder.run(args.rounds)?;
+    print!("seed {seed}\n{}", ladder.to_csv());
+    if let Some(path) = args.out {
+        let content = if path.ends_with(".csv") {
+            ladder.to_csv()
+        } else {
+            serde_json::to_string_pretty(&ladder.leaderboard())?
+        };
+        std::fs::write(&path, content)?;
+    }
+    Ok(())
+}
+
+fn tune(args: TuneArgs) -> Result<()> {
+    let opponents: [Strategy; 2] = args
+        .opponents
+        .try_into()
+        .map_err(|s: Vec<Strategy>| anyhow!("expect 2 opponents, got {}", s.len()))?;
+    let common = args.common;
+    let seed = common.seed();
+    let tuner = Tuner::new(
+        opponents,
+        common.rules,
+        seed,
+        common.games,
+        common.threads(),
+    );
+    let tuned = tuner.run(args.iterations)?;
+    println!(
+        "seed {seed}, best win rate {:.2}% on held-out deals against {:.2}% for the defaults ({:.2}% on the search deals): {:?}",
+        tuned.held_out_rate * 100.0,
+        tuned.default_rate * 100.0,
+        tuned.search_rate * 100.0,
+        tuned.weights,
+    );
+    if !tuned.is_better() {
+        println!(
+            "the defaults are not beaten on held-out deals, {} is left alone",
+            args.out
+        );
+        return Ok(());
+    }
+    std::fs::write(&args.out, serde_json::to_string_pretty(&tuned.weights)?)?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Ladder(args)) => ladder(args),
+        Some(Command::Tune(args)) => tune(args),
+        None => simulate(cli.line_up),
+    }
+}
@@ -3,12 +3,21 @@ use serde::{Deserialize, Serialize};
 #[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default, PartialOrd, Ord)]
 pub struct Card(pub u8);
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Pairing {
     Triplet(Card),
     Quadlet(Card),
 }
 
+impl Pairing {
+    pub fn score(&self) -> u8 {
+        match self {
+            Pairing::Triplet(_) => 2,
+            Pairing::Quadlet(_) => 6,
+        }
+    }
+}
+
 impl Card {
     pub fn is_same_kind(&self, other: &Card) -> bool {
         self.0 / 4 == other.0 / 4
@@ -0,0 +1,130 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// The preset reactions, one click away.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Emote {
+    Hello,
+    WellPlayed,
+    Thanks,
+    Oops,
+    Hurry,
+    Wow,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum Said {
+    Text(String),
+    Emote(Emote),
+}
+
+/// Something said at the table, or among the spectators.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ChatLine {
+    pub name: String,
+    /// `None` for a spectator
+    pub seat: Option<u8>,
+    pub said: Said,
+}
+
+/// The last lines of a chat, and how fast everyone talks.
+#[derive(Default)]
+pub struct Chat {
+    history: VecDeque<ChatLine>,
+    /// when each sender said their last lines, by sender
+    sent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl Chat {
+    /// the lines a newcomer gets
+    pub const HISTORY: usize = 50;
+    pub const MAX_LEN: usize = 200;
+    /// how many lines a sender says within `WINDOW`
+    const BURST: usize = 5;
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    /// Keep `line` from `sender`, unless it is too long or they talk too fast.
+    pub fn say(&mut self, sender: &str, line: &ChatLine, now: Instant) -> Result<()> {
+        if let Said::Text(text) = &line.said {
+            if text.trim().is_empty() {
+                bail!("nothing to say");
+            }
+            if text.chars().count() > Self::MAX_LEN {
+                bail!("lines are at most {} characters", Self::MAX_LEN);
+            }
+        }
+        // forget the lines out of the window, and whoever said no other
+        self.sent.retain(|_, sent| {
+            while sent
+                .front()
+                .is_some_and(|t| now.duration_since(*t) >= Self::WINDOW)
+            {
+                sent.pop_front();
+            }
+            !sent.is_empty()
+        });
+        let sent = self.sent.entry(sender.to_string()).or_default();
+        if sent.len() >= Self::BURST {
+            bail!("slow down");
+        }
+        sent.push_back(now);
+        if self.history.len() == Self::HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(line.clone());
+        Ok(())
+    }
+
+    pub fn history(&self) -> Vec<ChatLine> {
+        self.history.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> ChatLine {
+        ChatLine {
+            name: "alice".to_string(),
+            seat: Some(0),
+            said: Said::Text(text.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut chat = Chat::default();
+        let now = Instant::now();
+        assert!(chat.say("alice", &line(" "), now).is_err());
+        assert!(chat.say("alice", &line(&"x".repeat(201)), now).is_err());
+        for _ in 0..5 {
+            chat.say("alice", &line("hi"), now).unwrap();
+        }
+        assert!(chat.say("alice", &line("hi"), now).is_err());
+        // others still talk, and alice does once the window passed
+        chat.say("bob", &line("hi"), now).unwrap();
+        chat.say("alice", &line("hi"), now + Chat::WINDOW).unwrap();
+        assert_eq!(chat.history().len(), 7);
+        // bob went quiet and is forgotten
+        assert_eq!(chat.sent.len(), 1);
+    }
+
+    #[test]
+    fn test_history_is_short() {
+        let mut chat = Chat::default();
+        let start = Instant::now();
+        for i in 0..60 {
+            let now = start + Chat::WINDOW * i;
+            chat.say("alice", &line(&i.to_string()), now).unwrap();
+        }
+        let history = chat.history();
+        assert_eq!(history.len(), Chat::HISTORY);
+        assert_eq!(history[0], line("10"));
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::{Context, Result};
+use futures::prelude::*;
+use log::debug;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::{
+    agent::{Agent, Strategy},
+    card::Pairing,
+    game::{ClientMessage, GameState, Mode, ServerMessage},
+};
+
+/// A connection to `/api/ws/<room>` speaking the JSON protocol.
+pub struct Client {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Client {
+    /// Connect to `url`, e.g. `ws://localhost:3131/api/ws/<room>`.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let (socket, _) = connect_async(url)
+            .await
+            .with_context(|| format!("failed to connect to {url}"))?;
+        Ok(Self { socket })
+    }
+
+    /// Connect to `url` and say hello as `name`.
+    pub async fn join(url: &str, name: &str) -> Result<Self> {
+        let mut client = Self::connect(url).await?;
+        client
+            .send(ClientMessage::Hello {
+                name: name.to_string(),
+                user: None,
+                reservation: None,
+                spectate: false,
+            })
+            .await?;
+        Ok(client)
+    }
+
+    pub async fn send(&mut self, msg: ClientMessage) -> Result<()> {
+        let serialized = serde_json::to_string(&msg).expect("failed to serialize");
+        self.socket.send(Message::text(serialized)).await?;
+        Ok(())
+    }
+
+    /// the next message from the server, `None` once the connection is closed
+    pub async fn recv(&mut self) -> Result<Option<ServerMessage>> {
+        while let Some(message) = self.socket.next().await {
+            match message? {
+                Message::Text(text) => {
+                    let msg = serde_json::from_str(&text)
+                        .with_context(|| format!("failed to deserialize {text}"))?;
+                    return Ok(Some(msg));
+                }
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Plays a seat from the messages the server sends to it, deciding with an
+/// `Agent` just like the robots inside `GameState`.
+pub struct Bot {
+    pub agent: Agent,
+    turn: u8,
+}
+
+impl Bot {
+    pub fn new(strategy: Strategy) -> Self {
+        let mut agent = Agent::default();
+        agent.is_robot = true;
+        agent.set_strategy(strategy);
+        Self { agent, turn: 0 }
+    }
+
+    fn my_turn(&self) -> bool {
+        self.turn == self.agent.id
+    }
+
+    fn is_hu(&self) -> bool {
+        let score = self.agent.pairing.iter().map(|p| p.score()).sum();
+        GameState::is_hu(
+            &self.agent.hand,
+            score,
+            self.agent.jing,
+            self.agent.rules.min_hu_score,
+        )
+    }
+
+    /// whether the seat `id` sits on our right
+    fn is_right(&self, id: u8) -> bool {
+        (self.agent.id + 1) % 3 == id
+    }
+
+    fn discard(&mut self) -> ClientMessage {
+        ClientMessage::Discard {
+            card: self.agent.discard_card(),
+        }
+    }
+
+    /// a neighbour claimed a discard, which leaves the discards for its pairing
+    fn claimed(&mut self, pairing: Pairing) {
+        if self.my_turn() {
+            return;
+        }
+        let card = match pairing {
+            Pairing::Triplet(c) | Pairing::Quadlet(c) => c,
+        };
+        if self.is_right(self.turn) {
+            self.agent.player_right_pairing.push(pairing);
+        } else {
+            self.agent.player_left_pairing.push(pairing);
+        }
+        for out in [
+            &mut self.agent.out,
+            &mut self.agent.player_right_out,
+            &mut self.agent.player_left_out,
+        ] {
+            if out.last().is_some_and(|c| c.is_same_kind(&card)) {
+                out.pop();
+            }
+        }
+        self.agent.update_probability();
+    }
+
+    /// Update the agent with `msg` and return what to answer.
+    pub fn handle(&mut self, msg: &ServerMessage) -> Vec<ClientMessage> {
+        debug!("[bot {}] {msg:?}", self.agent.id);
+        match msg {
+            ServerMessage::Initial {
+                to,
+                cur_turn,
+                hand,
+                jing,
+                ..
+            } => {
+                self.agent.clear();
+                self.agent.id = to.expect("initial message without receiver");
+                self.agent.turn = self.agent.id;
+                self.agent.hand = hand.clone();
+                self.agent.jing = *jing;
+                self.agent.update_probability();
+                self.turn = *cur_turn;
+                vec![]
+            }
+            ServerMessage::Draw { card, .. } => {
+                self.agent.draw_card(*card);
+                // the server announces the hu right after the draw
+                if self.is_hu() {
+                    vec![]
+                } else {
+                    vec![self.discard()]
+                }
+            }
+            ServerMessage::Turn { turn, mode, .. } => {
+                self.turn = *turn;
+                if !self.my_turn() {
+                    return vec![];
+                }
+                match mode {
+                    Mode::Pao(card) => vec![ClientMessage::Pao {
+                        confirm: self.agent.pao_card(*card),
+                    }],
+                    Mode::Ding(card) => {
+                        if self.agent.ding_card(*card) {
+                            vec![ClientMessage::Ding { confirm: true }, self.discard()]
+                        } else {
+                            vec![ClientMessage::Ding { confirm: false }]
+                        }
+                    }
+                    Mode::Normal => vec![],
+                }
+            }
+            ServerMessage::Discard { seat, card, .. } => {
+                // our own discards are already in our `out`
+                if *seat == self.agent.id {
+                    return vec![];
+                }
+                if self.is_right(*seat) {
+                    self.agent.player_right_out.push(*card);
+                } else {
+                    self.agent.player_left_out.push(*card);
+                }
+                vec![]
+            }
+            ServerMessage::Pao { card, .. } => {
+                self.claimed(Pairing::Quadlet(*card));
+                vec![]
+            }
+            ServerMessage::Ding { card, .. } => {
+                self.claimed(Pairing::Triplet(*card));
+                vec![]
+            }
+            ServerMessage::Hu { .. } | ServerMessage::End { .. } => {
+                self.agent.clear();
+                vec![]
+            }
+            ServerMessage::Robot { .. }
+            | ServerMessage::Joined { .. }
+            | ServerMessage::Closed { .. }
+            | ServerMessage::Lobby { .. }
+            | ServerMessage::Refused { .. }
+            | ServerMessage::Kicked { .. }
+            | ServerMessage::Chat { .. }
+            | ServerMessage::ChatHistory { .. }
+            | ServerMessage::Resync { .. } => vec![],
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::{
+    agent::{Difficulty, Observation, Strategy},
+    card::{Card, Pairing},
+    game::{GameState, Mode},
+    rules::Rules,
+};
+
+/// the seat the learner plays, the robots sit on its right and left
+const SEAT: usize = 0;
+
+/// actions `0..24` discard a card of that kind
+pub const CLAIM: usize = 24;
+pub const PASS: usize = 25;
+pub const ACTIONS: usize = 26;
+
+/// hand, three discard histories, three pairing flags, jing, the claimable
+/// card, the pending Ding and Pao and the wall
+pub const OBSERVATION_SIZE: usize = 24 * 9 + 3;
+
+/// What the learner gets back after `reset` and `step`.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Step {
+    pub observation: Vec<f32>,
+    pub reward: f32,
+    pub done: bool,
+    /// `legal[a]` tells whether action `a` can be played now
+    pub legal: Vec<bool>,
+}
+
+/// A single-agent environment: the learner plays one seat, the other two
+/// are robots with the given strategies.
+///
+/// The learner is rewarded 1 for a hu, -1 when a robot hu's and 0 for a
+/// draw, all at the end of the hand.
+pub struct Env {
+    game: GameState,
+    done: bool,
+}
+
+impl Env {
+    pub fn new(opponents: [Strategy; 2], rules: Rules) -> Self {
+        let mut game = GameState::default();
+        game.set_rules(rules);
+        let seat = game.add_player().expect("a new table has free seats");
+        // the learner is always ready for the next hand
+        game.players[seat as usize].ready = true;
+        for strategy in opponents {
+            game.add_robot(Some(strategy), Difficulty::Hard, None)
+                .expect("a new table has free seats");
+        }
+        Self { game, done: true }
+    }
+
+    /// Deal a new hand, the same one for the same `seed`.
+    pub fn reset(&mut self, seed: u64) -> Result<Step> {
+        if !self.done {
+            self.game.end(true);
+        }
+        self.game.seed(seed);
+        self.game.start()?;
+        self.done = false;
+        Ok(self.advance())
+    }
+
+    pub fn step(&mut self, action: usize) -> Result<Step> {
+        if self.done {
+            bail!("the hand is over, call reset");
+        }
+        if !self.legal().get(action).copied().unwrap_or(false) {
+            bail!("illegal action {action}");
+        }
+        match action {
+            CLAIM | PASS => {
+                self.game.answer_claim(action == CLAIM)?;
+            }
+            kind => {
+                let card = *self.game.players[SEAT]
+                    .hand
+                    .iter()
+                    .find(|c| (c.0 / 4) as usize == kind)
+                    .expect("legal discard");
+                self.game.discard_card(SEAT, card)?;
+                self.game.next_turn(&card);
+            }
+        }
+        Ok(self.advance())
+    }
+
+    /// Play the robots until the learner has to decide or the hand ends.
+    fn advance(&mut self) -> Step {
+        loop {
+            if self.game.is_over() {
+                return self.finish();
+            }
+            if self.game.is_robot_turn() {
+                if let Some(card) = self.game.robot_turn(None) {
+                    self.game.next_turn(&card);
+                }
+                continue;
+            }
+            if self.game.mode() == Mode::Normal && !self.must_discard() {
+                self.game.draw_card();
+                if !self.game.is_over() && self.game.is_player_hu() {
+                    self.game.end(false);
+                }
+                continue;
+            }
+            return Step {
+                observation: self.observation(),
+                reward: 0.0,
+                done: false,
+                legal: self.legal(),
+            };
+        }
+    }
+
+    /// after a draw or a Ding the learner holds one card too many
+    fn must_discard(&self) -> bool {
+        let me = &self.game.players[SEAT];
+        me.hand.len() + 3 * me.pairing.len() == 20
+    }
+
+    fn finish(&mut self) -> Step {
+        self.done = true;
+        let reward = match self.game.winner {
+            Some(w) if w as usize == SEAT => 1.0,
+            Some(w) if w != u8::MAX => -1.0,
+            _ => 0.0,
+        };
+        Step {
+            observation: self.observation(),
+            reward,
+            done: true,
+            legal: vec![false; ACTIONS],
+        }
+    }
+
+    fn claim(&self) -> Option<Pairing> {
+        match self.game.mode() {
+            Mode::Ding(c) if !self.done => Some(Pairing::Triplet(c)),
+            Mode::Pao(c) if !self.done => Some(Pairing::Quadlet(c)),
+            _ => None,
+        }
+    }
+
+    fn legal(&self) -> Vec<bool> {
+        let mut legal = vec![false; ACTIONS];
+        if self.done {
+            return legal;
+        }
+        if self.claim().is_some() {
+            legal[CLAIM] = true;
+            legal[PASS] = true;
+        } else {
+            for c in &self.game.players[SEAT].hand {
+                legal[(c.0 / 4) as usize] = true;
+            }
+        }
+        legal
+    }
+
+    pub fn observation(&self) -> Vec<f32> {
+        encode(&self.game.observe(SEAT), self.claim())
+    }
+}
+
+/// Encode what a seat sees as `OBSERVATION_SIZE` numbers.
+pub fn encode(observation: &Observation, claim: Option<Pairing>) -> Vec<f32> {
+    let mut v = vec![0.0; OBSERVATION_SIZE];
+    let kind = |c: &Card| (c.0 / 4) as usize;
+    for c in &observation.hand {
+        v[kind(c)] += 1.0;
+    }
+    for (i, out) in [
+        &observation.out,
+        &observation.right_out,
+        &observation.left_out,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        for c in out {
+            v[24 * (1 + i) + kind(c)] += 1.0;
+        }
+    }
+    for (i, pairing) in [
+        &observation.pairing,
+        &observation.right_pairing,
+        &observation.left_pairing,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        for p in pairing {
+            let (Pairing::Triplet(c) | Pairing::Quadlet(c)) = p;
+            v[24 * (4 + i) + kind(c)] = 1.0;
+        }
+    }
+    v[24 * 7 + kind(&observation.jing)] = 1.0;
+    if let Some(claim) = claim {
+        let (Pairing::Triplet(c) | Pairing::Quadlet(c)) = claim;
+        v[24 * 8 + kind(&c)] = 1.0;
+        match claim {
+            Pairing::Triplet(_) => v[24 * 9] = 1.0,
+            Pairing::Quadlet(_) => v[24 * 9 + 1] = 1.0,
+        }
+    }
+    v[24 * 9 + 2] = observation.wall as f32;
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// play the first legal action until the hand ends
+    fn play(env: &mut Env, seed: u64) -> Vec<Step> {
+        let mut steps = vec![env.reset(seed).unwrap()];
+        while !steps.last().unwrap().done {
+            let legal = &steps.last().unwrap().legal;
+            let action = legal.iter().position(|&l| l).unwrap();
+            steps.push(env.step(action).unwrap());
+        }
+        steps
+    }
+
+    #[test]
+    fn test_env_plays_a_hand() {
+        let mut env = Env::new([Strategy::Level1, Strategy::Random], Rules::default());
+        for seed in 0..3 {
+            let steps = play(&mut env, seed);
+            assert!(steps.len() > 1);
+            for step in &steps {
+                assert_eq!(step.observation.len(), OBSERVATION_SIZE);
+                assert_eq!(step.legal.len(), ACTIONS);
+            }
+            assert!(env.step(0).is_err());
+            env.game.replay().unwrap().verify().unwrap();
+            assert_eq!(steps, play(&mut env, seed));
+        }
+    }
+
+    #[test]
+    fn test_illegal_action_is_refused() {
+        let mut env = Env::new([Strategy::Random, Strategy::Random], Rules::default());
+        let step = env.reset(1).unwrap();
+        let illegal = step.legal.iter().position(|&l| !l).unwrap();
+        assert!(env.step(illegal).is_err());
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::card::{Card, Pairing};
+
+/// the lowest score a hand needs to hu
+pub const MIN_HU_SCORE: u8 = 12;
+/// number of melds (pairings included) in a winning hand
+const MELDS: usize = 6;
+
+pub fn shun_score(cat: u8, jing: Card) -> u8 {
+    let mut score = 0;
+    // 上大人
+    if cat == 0 {
+        score += 4;
+    }
+    for x in [cat * 3, cat * 3 + 1, cat * 3 + 2] {
+        if jing.is_same_kind(&Card(x * 4)) {
+            score += 4;
+        }
+    }
+    score
+}
+
+pub fn ke_score(kind: u8, jing: Card) -> u8 {
+    let mut score = 4;
+    if kind == 0 {
+        score += 8;
+    }
+    if jing.is_same_kind(&Card(kind * 4)) {
+        score += 8;
+    }
+    score
+}
+
+/// A meld of a complete hand.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Meld {
+    /// one card of each kind of the category
+    Shun(u8),
+    /// three cards of the kind
+    Ke(u8),
+    Pairing(Pairing),
+}
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Breakdown {
+    /// the melds with the score each adds
+    pub melds: Vec<(Meld, u8)>,
+    /// the two kinds left over, of the same category
+    pub eye: (u8, u8),
+    pub score: u8,
+}
+
+/// Split `hand` the way the rules do, sequences first and then triplets,
+/// and score it with the pairings. Returns `None` unless exactly two cards
+/// of different kinds of one category are left over.
+pub fn breakdown(hand: &[Card], pairing: &[Pairing], jing: Card) -> Option<Breakdown> {
+    let mut cnt = [0u8; 24];
+    for c in hand {
+        cnt[(c.0 / 4) as usize] += 1;
+    }
+    let mut melds: Vec<(Meld, u8)> = pairing
+        .iter()
+        .map(|&p| (Meld::Pairing(p), p.score()))
+        .collect();
+    for cat in 0..8u8 {
+        let k = cat as usize * 3;
+        while cnt[k] > 0 && cnt[k + 1] > 0 && cnt[k + 2] > 0 {
+            for n in &mut cnt[k..k + 3] {
+                *n -= 1;
+            }
+            melds.push((Meld::Shun(cat), shun_score(cat, jing)));
+        }
+    }
+    for kind in 0..24u8 {
+        if cnt[kind as usize] >= 3 {
+            cnt[kind as usize] -= 3;
+            melds.push((Meld::Ke(kind), ke_score(kind, jing)));
+        }
+    }
+    let left: Vec<u8> = (0..24u8).filter(|&k| cnt[k as usize] > 0).collect();
+    match left[..] {
+        [a, b] if a / 3 == b / 3 => Some(Breakdown {
+            score: melds.iter().map(|(_, s)| s).sum(),
+            melds,
+            eye: (a, b),
+        }),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HandValue {
+    /// cards missing from the closest winning hand, 0 means the hand is hu
+    pub distance: u8,
+    /// the best score among the closest winning hands
+    pub score: u8,
+}
+
+impl HandValue {
+    pub fn is_better_than(&self, other: &HandValue) -> bool {
+        self.distance < other.distance
+            || (self.distance == other.distance && self.score > other.score)
+    }
+}
+
+/// one way to complete a category: how many melds it contributes, whether it
+/// holds the final pair, how many cards are missing and the score it adds
+#[derive(Clone, Copy)]
+struct Completion {
+    melds: usize,
+    eye: usize,
+    missing: u8,
+    score: u8,
+}
+
+/// Split the target counts of one category the same way `is_hu` does,
+/// sequences first and then triplets. Only splits leaving nothing or a
+/// single pair of different kinds can be part of a winning hand.
+fn split_category(cat: u8, target: [u8; 3], jing: Card) -> Option<(usize, usize, u8)> {
+    let shun = *target.iter().min().unwrap();
+    let mut melds = shun as usize;
+    let mut score = shun * shun_score(cat, jing);
+    let mut left = target.map(|n| n - shun);
+    for (k, n) in left.iter_mut().enumerate() {
+        if *n >= 3 {
+            *n -= 3;
+            melds += 1;
+            score += ke_score(cat * 3 + k as u8, jing);
+        }
+    }
+    match left {
+        [0, 0, 0] => Some((melds, 0, score)),
+        [1, 1, 0] | [1, 0, 1] | [0, 1, 1] => Some((melds, 1, score)),
+        _ => None,
+    }
+}
+
+fn category_options(cat: u8, hand: [u8; 3], available: [u8; 3], jing: Card) -> Vec<Completion> {
+    let mut options = vec![];
+    for a in 0..=4 {
+        for b in 0..=4 {
+            for c in 0..=4 {
+                let target: [u8; 3] = [a, b, c];
+                let mut missing = 0;
+                let mut possible = true;
+                for k in 0..3 {
+                    let need = target[k].saturating_sub(hand[k]);
+                    if need > available[k] {
+                        possible = false;
+                    }
+                    missing += need;
+                }
+                if !possible {
+                    continue;
+                }
+                if let Some((melds, eye, score)) = split_category(cat, target, jing) {
+                    options.push(Completion {
+                        melds,
+                        eye,
+                        missing,
+                        score,
+                    });
+                }
+            }
+        }
+    }
+    options
+}
+
+/// keep only the (missing, score) pairs that are not worse in both
+fn prune(values: &mut Vec<(u8, u8)>) {
+    values.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    let mut best: Option<u8> = None;
+    values.retain(|&(_, score)| {
+        if best.is_none_or(|b| score > b) {
+            best = Some(score);
+            true
+        } else {
+            false
+        }
+    });
+}
+
+/// Evaluate how far `hand` is from a hu scoring at least `min_score` with
+/// the given pairings.
+///
+/// `remaining` maps each kind to the number of its cards that may still be
+/// drawn; when it is `None` only the four copies of a kind limit the search.
+/// Returns `None` if no winning hand can be reached any more.
+pub fn evaluate(
+    hand: &[Card],
+    pairing: &[Pairing],
+    jing: Card,
+    remaining: Option<&HashMap<u8, u8>>,
+    min_score: u8,
+) -> Option<HandValue> {
+    if pairing.len() > MELDS {
+        return None;
+    }
+    let melds = MELDS - pairing.len();
+    let mut cnt = [0u8; 24];
+    for c in hand {
+        cnt[(c.0 / 4) as usize] += 1;
+    }
+    let available = |kind: usize| match remaining {
+        Some(remaining) => remaining.get(&(kind as u8)).copied().unwrap_or(0),
+        None => 4 - cnt[kind].min(4),
+    };
+
+    let base = pairing.iter().map(|p| p.score()).sum::<u8>();
+    // frontier[m][e] holds the best (missing, score) pairs using m melds and
+    // e final pairs over the categories seen so far
+    let mut frontier = vec![vec![vec![]; 2]; melds + 1];
+    frontier[0][0].push((0u8, base));
+    for cat in 0..8u8 {
+        let k = cat as usize * 3;
+        let options = category_options(
+            cat,
+            [cnt[k], cnt[k + 1], cnt[k + 2]],
+            [available(k), available(k + 1), available(k + 2)],
+            jing,
+        );
+        let mut next = vec![vec![vec![]; 2]; melds + 1];
+        for m in 0..=melds {
+            for e in 0..2 {
+                for &(missing, score) in &frontier[m][e] {
+                    for o in &options {
+                        if m + o.melds > melds || e + o.eye > 1 {
+                            continue;
+                        }
+                        next[m + o.melds][e + o.eye].push((missing + o.missing, score + o.score));
+                    }
+                }
+            }
+        }
+        for values in next.iter_mut().flatten() {
+            prune(values);
+        }
+        frontier = next;
+    }
+
+    frontier[melds][1]
+        .iter()
+        .find(|(_, score)| *score >= min_score)
+        .map(|&(distance, score)| HandValue { distance, score })
+}
+
+/// Evaluate the hand after claiming the discarded `claim`.
+///
+/// A Ding is followed by a discard, so the best discard is assumed. A Pao is
+/// followed by a draw, which is left out of the distance.
+pub fn evaluate_claim(
+    hand: &[Card],
+    pairing: &[Pairing],
+    jing: Card,
+    remaining: Option<&HashMap<u8, u8>>,
+    min_score: u8,
+    claim: Pairing,
+) -> Option<HandValue> {
+    let card = match claim {
+        Pairing::Triplet(c) | Pairing::Quadlet(c) => c,
+    };
+    let hand: Vec<Card> = hand
+        .iter()
+        .filter(|c| !c.is_same_kind(&card))
+        .copied()
+        .collect();
+    let mut pairing = pairing.to_vec();
+    pairing.push(claim);
+    match claim {
+        Pairing::Quadlet(_) => evaluate(&hand, &pairing, jing, remaining, min_score),
+        Pairing::Triplet(_) => {
+            let mut best: Option<HandValue> = None;
+            let mut hand = hand;
+            for i in 0..hand.len() {
+                hand.swap(0, i);
+                let value = evaluate(&hand[1..], &pairing, jing, remaining, min_score);
+                if let Some(value) = value {
+                    if best.is_none_or(|b| value.is_better_than(&b)) {
+                        best = Some(value);
+                    }
+                }
+                hand.swap(0, i);
+            }
+            best
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::GameState;
+
+    use super::*;
+
+    fn cards(hand: &[u8]) -> Vec<Card> {
+        hand.iter().map(|&n| Card(n)).collect()
+    }
+
+    #[test]
+    fn test_evaluate_agrees_with_hu() {
+        // 0 1 2 / 0 1 2 / 3 3 3 / 3 4 5 / 6 7 8 / 6 7 8 / 8 6
+        let hand = cards(&[
+            0, 4, 8, 1, 5, 9, 12, 13, 14, 15, 16, 20, 24, 28, 32, 25, 29, 33, 34, 26,
+        ]);
+        assert!(GameState::is_hu(&hand, 0, Card(90), MIN_HU_SCORE));
+        let value = evaluate(&hand, &[], Card(90), None, MIN_HU_SCORE).unwrap();
+        assert_eq!(value.distance, 0);
+        assert!(value.score >= MIN_HU_SCORE);
+
+        // the same hand waiting on its last card
+        let value = evaluate(&hand[..19], &[], Card(90), None, MIN_HU_SCORE).unwrap();
+        assert_eq!(value.distance, 1);
+
+        // 0 1 2 / 0 1 2 / 3 3 3 / 3 4 5 / 6 7 8 / 6 7 8 / 8 9
+        let hand = cards(&[
+            0, 4, 8, 1, 5, 9, 12, 13, 14, 15, 16, 20, 24, 28, 32, 25, 29, 33, 34, 36,
+        ]);
+        assert!(!GameState::is_hu(&hand, 0, Card(90), MIN_HU_SCORE));
+        assert!(
+            evaluate(&hand, &[], Card(90), None, MIN_HU_SCORE)
+                .unwrap()
+                .distance
+                > 0
+        );
+    }
+
+    #[test]
+    fn test_breakdown() {
+        // 0 1 2 / 0 1 2 / 3 3 3 / 3 4 5 / 6 7 8 / 6 7 8 / 8 6
+        let hand = cards(&[
+            0, 4, 8, 1, 5, 9, 12, 13, 14, 15, 16, 20, 24, 28, 32, 25, 29, 33, 34, 26,
+        ]);
+        let b = breakdown(&hand, &[], Card(90)).unwrap();
+        assert_eq!(b.eye, (6, 8));
+        assert_eq!(b.melds.len(), 6);
+        assert!(b.melds.contains(&(Meld::Ke(3), ke_score(3, Card(90)))));
+        assert_eq!(b.score, b.melds.iter().map(|(_, s)| s).sum::<u8>());
+
+        // a sequence swapped for a claimed triplet
+        let pairing = [Pairing::Triplet(Card(0))];
+        let b = breakdown(&hand[3..], &pairing, Card(90)).unwrap();
+        assert!(b
+            .melds
+            .contains(&(Meld::Pairing(pairing[0]), pairing[0].score())));
+        assert!(breakdown(&hand[4..], &pairing, Card(90)).is_none());
+    }
+
+    #[test]
+    fn test_evaluate_respects_remaining() {
+        // waiting on kind 6 only
+        let hand = cards(&[
+            0, 4, 8, 1, 5, 9, 12, 13, 14, 15, 16, 20, 24, 28, 32, 25, 29, 33, 34,
+        ]);
+        let mut remaining: HashMap<u8, u8> = (0..24).map(|k| (k, 4)).collect();
+        let open = evaluate(&hand, &[], Card(90), Some(&remaining), MIN_HU_SCORE).unwrap();
+        remaining.insert(6, 0);
+        remaining.insert(7, 0);
+        let closed = evaluate(&hand, &[], Card(90), Some(&remaining), MIN_HU_SCORE).unwrap();
+        assert!(open.is_better_than(&closed));
+    }
+
+    #[test]
+    fn test_claim_breaking_shun_does_not_help() {
+        // 0 1 2 / 0 1 2 / 3 4 5 / 3 4 5 / 6 7 8 / 6 7 8 / 9
+        let hand = cards(&[
+            0, 4, 8, 1, 5, 9, 12, 16, 20, 13, 17, 21, 24, 28, 32, 25, 29, 33, 36,
+        ]);
+        let without = evaluate(&hand, &[], Card(90), None, MIN_HU_SCORE).unwrap();
+        let with = evaluate_claim(
+            &hand,
+            &[],
+            Card(90),
+            None,
+            MIN_HU_SCORE,
+            Pairing::Triplet(Card(2)),
+        );
+        assert!(with.is_none_or(|with| !with.is_better_than(&without)));
+    }
+}
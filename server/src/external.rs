@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use log::warn;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::{Observation, Strategy},
+    card::{Card, Pairing},
+};
+
+/// a bot failing this many times is stopped and replaced by its fallback
+const MAX_FAILURES: u8 = 3;
+
+fn default_timeout() -> u64 {
+    1000
+}
+
+/// How to launch an external bot, as listed in the bots config file.
+#[derive(Deserialize, Clone, Debug)]
+pub struct BotConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "default_timeout")]
+    pub timeout_ms: u64,
+    /// the built-in strategy used whenever the bot misbehaves
+    #[serde(default)]
+    pub fallback: Strategy,
+}
+
+/// Look up the bot registered as `name` in the JSON file named by
+/// `SHANGDAREN_BOTS`, or `bots.json` by default.
+pub fn registered(name: &str) -> Result<BotConfig> {
+    let path = std::env::var("SHANGDAREN_BOTS").unwrap_or("bots.json".to_string());
+    let content =
+        std::fs::read_to_string(&path).with_context(|| format!("failed to read {path}"))?;
+    let mut bots: HashMap<String, BotConfig> =
+        serde_json::from_str(&content).with_context(|| format!("failed to parse {path}"))?;
+    bots.remove(name)
+        .with_context(|| format!("bot {name} is not registered in {path}"))
+}
+
+/// What the bot is asked, one JSON object per line on its stdin.
+#[derive(Serialize)]
+enum Query<'a> {
+    Discard {
+        observation: &'a Observation,
+    },
+    Ding {
+        card: Card,
+        observation: &'a Observation,
+    },
+    Pao {
+        card: Card,
+        observation: &'a Observation,
+    },
+}
+
+/// What the bot answers, one JSON object per line on its stdout.
+#[derive(Deserialize, Debug)]
+enum Reply {
+    Discard { card: Card },
+    Ding { confirm: bool },
+    Pao { confirm: bool },
+}
+
+/// A decision a robot asks its external bot for.
+#[derive(Clone, Copy, Debug)]
+pub enum Question {
+    Discard,
+    Claim(Pairing),
+}
+
+/// The bot's answer to a `Question`.
+#[derive(Clone, Copy, Debug)]
+pub enum Answer {
+    Discard(Card),
+    Claim(bool),
+    /// the bot misbehaved, the fallback decides
+    Failed,
+}
+
+pub struct ExternalBot {
+    child: Child,
+    stdin: ChildStdin,
+    // the mutex only makes the receiver `Sync`, it is never contended
+    lines: Mutex<Receiver<String>>,
+    timeout: Duration,
+    failures: u8,
+    pub fallback: Strategy,
+}
+
+impl ExternalBot {
+    pub fn spawn(config: &BotConfig) -> Result<Self> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to launch bot {}", config.command))?;
+        let stdin = child.stdin.take().context("missing bot stdin")?;
+        let stdout = child.stdout.take().context("missing bot stdout")?;
+        // read on a thread of its own so that a silent bot can time out
+        let (tx, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let fallback = match config.fallback {
+            Strategy::External(_) => Strategy::default(),
+            ref s => s.clone(),
+        };
+        Ok(Self {
+            child,
+            stdin,
+            lines: Mutex::new(lines),
+            timeout: Duration::from_millis(config.timeout_ms),
+            failures: 0,
+            fallback,
+        })
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.failures < MAX_FAILURES
+    }
+
+    pub fn discard(&mut self, observation: &Observation) -> Option<Card> {
+        match self.ask(&Query::Discard { observation })? {
+            Reply::Discard { card } => Some(card),
+            reply => self.fail(&format!("expect Discard, got {reply:?}")),
+        }
+    }
+
+    pub fn claim(&mut self, claim: Pairing, observation: &Observation) -> Option<bool> {
+        let query = match claim {
+            Pairing::Triplet(card) => Query::Ding { card, observation },
+            Pairing::Quadlet(card) => Query::Pao { card, observation },
+        };
+        match (claim, self.ask(&query)?) {
+            (Pairing::Triplet(_), Reply::Ding { confirm })
+            | (Pairing::Quadlet(_), Reply::Pao { confirm }) => Some(confirm),
+            (_, reply) => self.fail(&format!("expect {claim:?} answer, got {reply:?}")),
+        }
+    }
+
+    /// Ask `question`, blocking until the bot answers or times out.
+    pub fn answer(&mut self, question: Question, observation: &Observation) -> Answer {
+        match question {
+            Question::Discard => self
+                .discard(observation)
+                .map_or(Answer::Failed, Answer::Discard),
+            Question::Claim(claim) => self
+                .claim(claim, observation)
+                .map_or(Answer::Failed, Answer::Claim),
+        }
+    }
+
+    /// Count a misbehaviour, the caller falls back for this decision.
+    pub fn fail<T>(&mut self, reason: &str) -> Option<T> {
+        self.failures += 1;
+        warn!(
+            "external bot failed ({}/{MAX_FAILURES}): {reason}",
+            self.failures
+        );
+        if !self.is_alive() {
+            self.child.kill().ok();
+        }
+        None
+    }
+
+    fn ask(&mut self, query: &Query) -> Option<Reply> {
+        if !self.is_alive() {
+            return None;
+        }
+        // drop answers that arrived after an earlier timeout
+        let lines = self.lines.get_mut();
+        while lines.try_recv().is_ok() {}
+        let line = serde_json::to_string(query).expect("failed to serialize");
+        if let Err(e) = writeln!(self.stdin, "{line}").and_then(|_| self.stdin.flush()) {
+            return self.fail(&format!("cannot write to bot: {e}"));
+        }
+        let reply = match self.lines.get_mut().recv_timeout(self.timeout) {
+            Ok(reply) => reply,
+            Err(e) => return self.fail(&format!("no answer: {e}")),
+        };
+        match serde_json::from_str(&reply) {
+            Ok(reply) => Some(reply),
+            Err(e) => self.fail(&format!("invalid answer {reply:?}: {e}")),
+        }
+    }
+}
+
+impl Drop for ExternalBot {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bot(script: &str, timeout_ms: u64) -> ExternalBot {
+        ExternalBot::spawn(&BotConfig {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            timeout_ms,
+            fallback: Strategy::Level1,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_external_bot_answers() {
+        let mut bot = bot(
+            r#"while read line; do echo '{"Pao": {"confirm": true}}'; done"#,
+            1000,
+        );
+        let observation = Observation::default();
+        assert_eq!(
+            bot.claim(Pairing::Quadlet(Card(3)), &observation),
+            Some(true)
+        );
+        // a wrong answer counts as a failure
+        assert_eq!(bot.discard(&observation), None);
+        assert!(bot.is_alive());
+    }
+
+    #[test]
+    fn test_external_bot_times_out() {
+        let mut bot = bot("sleep 5", 50);
+        let observation = Observation::default();
+        for _ in 0..MAX_FAILURES {
+            assert_eq!(bot.discard(&observation), None);
+        }
+        assert!(!bot.is_alive());
+    }
+}
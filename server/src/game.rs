@@ -1,24 +1,52 @@
 use std::{
-    collections::HashMap,
-    sync::atomic::{AtomicU8, Ordering},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
-    agent::{Agent, Strategy},
+    agent::{Agent, Difficulty, Observation, Strategy},
     card::{Card, Pairing},
+    chat::{Chat, ChatLine, Emote, Said},
+    eval,
+    external::Question,
+    history::ReplayStore,
+    outbox::{Inbox, Outbox},
+    replay::{self, Event, Replay, Seat},
+    room::{RoomInfo, SeatInfo, Settings},
+    rules::{self, Rules},
+    stats::{SeatResult, Stats},
+    weights::Weights,
 };
 use anyhow::{bail, Context, Ok, Result};
 use futures::prelude::*;
 use log::{debug, warn};
-use parking_lot::RwLock;
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use tokio::sync::broadcast::{self, Sender};
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, oneshot, watch,
+    },
+    time,
+};
 use warp::ws::{Message, WebSocket};
 
+/// A room's game as its connections see it: what they ask goes to the task
+/// owning the table, what the table says comes out of the outbox.
 pub struct Game {
-    count: AtomicU8,
-    state: RwLock<GameState>,
-    connection: broadcast::Sender<ServerMessage>,
+    commands: mpsc::UnboundedSender<Command>,
+    outbox: Arc<Outbox>,
+    /// the room as the table last left it
+    info: watch::Receiver<RoomInfo>,
+    /// what the spectators say, the seats don't hear it
+    spectators: broadcast::Sender<ServerMessage>,
+    spectator_chat: Mutex<Chat>,
+    /// tells the spectators apart for the rate limit
+    next_spectator: AtomicU64,
 }
 
 pub struct GameState {
@@ -30,10 +58,39 @@ pub struct GameState {
     prev_turn: Option<u8>,
     jing: Card,
     mode: Mode,
+    /// the robot on turn has drawn or claimed and only has to discard
+    owes_discard: bool,
     test: bool,
+    #[allow(unused)]
     pub training: bool,
+    pub rules: Rules,
+    rng: StdRng,
+    /// the seed of the next deal, a fresh one is drawn when it is not set
+    seed: Option<u64>,
     pub winner: Option<u8>,
+    /// the score of the winning hand, until the next `start`
+    pub winning_score: Option<u8>,
+    /// the hand being played, or the last one until the next `start`
+    replay: Option<Replay>,
+    /// the room the game is played in, recorded in the replays
+    pub room: String,
+    /// the room is private, so are its replays
+    pub private: bool,
+    /// where to keep the replay of every hand
+    pub replays: Option<Arc<dyn ReplayStore>>,
+    /// where the players with an account have their hands counted
+    pub stats: Option<Arc<Stats>>,
+    /// the seat of the human running the room, the first one seated
+    pub host: Option<u8>,
 }
+/// How far `GameState::robot_step` got.
+pub enum RobotStep {
+    /// the robot discarded the card, or its turn ended without a discard
+    Played(Option<Card>),
+    /// the robot waits for its external bot to answer
+    Ask(Question),
+}
+
 #[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Mode {
     Pao(Card),
@@ -42,20 +99,62 @@ pub enum Mode {
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
-enum ClientMessage {
+pub enum ClientMessage {
+    /// the first message on a connection, `user` defaults to `name`
+    Hello {
+        name: String,
+        #[serde(default)]
+        user: Option<String>,
+        /// the seat matchmaking kept for this player
+        #[serde(default)]
+        reservation: Option<String>,
+        /// watch the table instead of taking a seat
+        #[serde(default)]
+        spectate: bool,
+    },
     Ready(bool),
+    /// deal the fixed test hands, only the tests can ask for it
+    #[cfg(test)]
     Test(bool),
-    AddRobot(bool),
+    AddRobot {
+        #[serde(default)]
+        strategy: Option<Strategy>,
+        #[serde(default)]
+        difficulty: Difficulty,
+        #[serde(default)]
+        name: Option<String>,
+    },
     Start(bool),
-    Discard { card: Card },
-    Ding { confirm: bool },
-    Pao { confirm: bool },
+    /// the host gives the seat of a robot back to newcomers
+    RemoveRobot {
+        seat: u8,
+    },
+    /// the host changes the rules of the next hands
+    SetRules(Rules),
+    /// the host hangs up on the human at `seat`, a stand-in takes over
+    Kick {
+        seat: u8,
+    },
+    Chat {
+        text: String,
+    },
+    Emote(Emote),
+    Discard {
+        card: Card,
+    },
+    Ding {
+        confirm: bool,
+    },
+    Pao {
+        confirm: bool,
+    },
 }
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum ServerMessage {
     Turn {
         to: Option<u8>,
         turn: u8,
+        name: String,
         mode: Mode,
     },
     Initial {
@@ -63,6 +162,8 @@ pub enum ServerMessage {
         cur_turn: u8,
         hand: Vec<Card>,
         jing: Card,
+        /// the names of the seats
+        names: Vec<String>,
     },
     Draw {
         to: Option<u8>,
@@ -70,22 +171,77 @@ pub enum ServerMessage {
     },
     Pao {
         to: Option<u8>,
+        seat: u8,
+        name: String,
         card: Card,
     },
     Ding {
         to: Option<u8>,
+        seat: u8,
+        name: String,
         card: Card,
     },
     Discard {
         to: Option<u8>,
+        seat: u8,
+        name: String,
         card: Card,
     },
     Hu {
         to: Option<u8>,
+        seat: u8,
+        name: String,
     },
     End {
         to: Option<u8>,
     },
+    Robot {
+        to: Option<u8>,
+        id: u8,
+        name: String,
+        strategy: Strategy,
+        difficulty: Difficulty,
+    },
+    /// someone sat down
+    Joined {
+        to: Option<u8>,
+        id: u8,
+        name: String,
+    },
+    /// the room was closed, the server hangs up after it
+    Closed {
+        to: Option<u8>,
+    },
+    /// who sits where and who is ready, whenever it changes
+    Lobby {
+        to: Option<u8>,
+        host: Option<u8>,
+        rules: Rules,
+        seats: Vec<SeatInfo>,
+    },
+    /// what a player asked for was not done
+    Refused {
+        to: Option<u8>,
+        reason: String,
+    },
+    /// the host sent the player away, the server hangs up after it
+    Kicked {
+        to: Option<u8>,
+    },
+    Chat {
+        to: Option<u8>,
+        line: ChatLine,
+    },
+    /// the last lines said, for whoever just came in
+    ChatHistory {
+        to: Option<u8>,
+        lines: Vec<ChatLine>,
+    },
+    /// the room as it is now, for a spectator who fell behind
+    Resync {
+        to: Option<u8>,
+        room: RoomInfo,
+    },
 }
 
 impl From<ServerMessage> for Message {
@@ -106,6 +262,15 @@ impl ServerMessage {
             ServerMessage::Ding { to, .. } => to.is_none(),
             ServerMessage::Hu { to, .. } => to.is_none(),
             ServerMessage::End { to, .. } => to.is_none(),
+            ServerMessage::Robot { to, .. } => to.is_none(),
+            ServerMessage::Joined { to, .. } => to.is_none(),
+            ServerMessage::Closed { to } => to.is_none(),
+            ServerMessage::Lobby { to, .. } => to.is_none(),
+            ServerMessage::Refused { to, .. } => to.is_none(),
+            ServerMessage::Kicked { to } => to.is_none(),
+            ServerMessage::Chat { to, .. } => to.is_none(),
+            ServerMessage::ChatHistory { to, .. } => to.is_none(),
+            ServerMessage::Resync { to, .. } => to.is_none(),
         }
     }
 
@@ -119,16 +284,15 @@ impl ServerMessage {
             ServerMessage::Ding { to, .. } => *to,
             ServerMessage::Hu { to, .. } => *to,
             ServerMessage::End { to, .. } => *to,
-        }
-    }
-}
-
-impl Default for Game {
-    fn default() -> Self {
-        Self {
-            count: Default::default(),
-            state: Default::default(),
-            connection: broadcast::channel(16).0,
+            ServerMessage::Robot { to, .. } => *to,
+            ServerMessage::Joined { to, .. } => *to,
+            ServerMessage::Closed { to } => *to,
+            ServerMessage::Lobby { to, .. } => *to,
+            ServerMessage::Refused { to, .. } => *to,
+            ServerMessage::Kicked { to } => *to,
+            ServerMessage::Chat { to, .. } => *to,
+            ServerMessage::ChatHistory { to, .. } => *to,
+            ServerMessage::Resync { to, .. } => *to,
         }
     }
 }
@@ -137,10 +301,7 @@ impl Default for GameState {
     fn default() -> Self {
         Self {
             players: vec![],
-            remaining_cards: (0..Self::TOTAL)
-                .into_iter()
-                .map(|n| Card(n as u8))
-                .collect(),
+            remaining_cards: (0..Self::TOTAL).map(|n| Card(n as u8)).collect(),
             round: 0,
             turn: 0,
             prev_turn: None,
@@ -148,39 +309,228 @@ impl Default for GameState {
             mode: Mode::Normal,
             test: false,
             training: false,
+            rules: Rules::default(),
+            owes_discard: false,
+            rng: StdRng::from_entropy(),
+            seed: None,
             winner: None,
+            winning_score: None,
+            replay: None,
+            room: String::new(),
+            private: false,
+            replays: None,
+            stats: None,
+            host: None,
         }
     }
 }
 impl GameState {
-    const TOTAL: usize = 96;
+    pub const TOTAL: usize = 96;
     const PLAYER_NUM: u8 = 3;
-    pub fn add_player(&mut self) {
+    /// longest name shown to the other players, in characters
+    pub const MAX_NAME: usize = 24;
+    /// Seat a human and return their seat. Between hands they take over
+    /// the seat of a stand-in.
+    pub fn add_player(&mut self) -> Result<u8> {
+        let seat = match self.players.iter().position(|p| p.stand_in) {
+            Some(seat) if !self.is_playing() => seat,
+            _ if self.players.len() < Self::PLAYER_NUM as usize => {
+                self.players.push(Agent::default());
+                self.players.len() - 1
+            }
+            _ => bail!("the table is full"),
+        };
         let mut player = Agent::default();
-        player.id = self.players.len() as u8;
-        self.players.push(player)
+        player.id = seat as u8;
+        player.name = format!("player {seat}");
+        player.rules = self.rules;
+        self.players[seat] = player;
+        if self.host.is_none() {
+            self.host = Some(seat as u8);
+        }
+        Ok(seat as u8)
+    }
+
+    /// the seat of a human, not a robot nor a stand-in
+    fn is_human(&self, seat: u8) -> bool {
+        self.players.get(seat as usize).is_some_and(|p| !p.is_robot)
+    }
+
+    /// Let a robot stand in for the human at `seat` who left, so that the
+    /// other seats keep theirs. It plays on from what the seat could see.
+    pub fn leave(&mut self, seat: u8) -> ServerMessage {
+        let index = seat as usize;
+        let human = std::mem::take(&mut self.players[index]);
+        let mut agent = self.robot(seat, None, Difficulty::Normal, Some(human.name));
+        agent.stand_in = true;
+        // a hand left halfway still counts
+        agent.account = human.account.filter(|_| self.is_playing());
+        if self.is_playing() {
+            let right = &self.players[(index + 1) % 3];
+            agent.player_right_out = right.out.clone();
+            agent.player_right_pairing = right.pairing.clone();
+            let left = &self.players[(index + 2) % 3];
+            agent.player_left_out = left.out.clone();
+            agent.player_left_pairing = left.pairing.clone();
+            agent.hand = human.hand;
+            agent.out = human.out;
+            agent.pairing = human.pairing;
+            agent.jing = self.jing;
+            agent.wall = human.wall;
+            agent.update_probability();
+            if self.turn == seat && self.mode == Mode::Normal {
+                // a human on turn has drawn already
+                self.owes_discard = agent.hand.len() + 3 * agent.pairing.len() == 20;
+            }
+        }
+        let msg = Self::robot_message(&agent);
+        self.players[index] = agent;
+        if self.host == Some(seat) {
+            // the next human along runs the room
+            self.host = (1..3).map(|i| (seat + i) % 3).find(|&s| self.is_human(s));
+        }
+        msg
+    }
+
+    /// Give the seat of a robot back to newcomers, between hands.
+    pub fn remove_robot(&mut self, seat: u8) -> Result<ServerMessage> {
+        if self.is_playing() {
+            bail!("robots stay until the hand is over");
+        }
+        match self.players.get(seat as usize) {
+            Some(p) if p.is_robot && !p.stand_in => {}
+            _ => bail!("no robot at seat {seat}"),
+        }
+        let mut agent = self.robot(seat, None, Difficulty::Normal, None);
+        agent.stand_in = true;
+        let msg = Self::robot_message(&agent);
+        self.players[seat as usize] = agent;
+        Ok(msg)
+    }
+
+    /// the seats as everyone sees them
+    pub fn seats(&self) -> Vec<SeatInfo> {
+        self.players
+            .iter()
+            .map(|p| SeatInfo {
+                name: p.name.clone(),
+                strategy: p.is_robot.then(|| p.strategy.clone()),
+                stand_in: p.stand_in,
+                ready: p.ready,
+                hand: p.hand.len(),
+                out: p.out.clone(),
+                pairing: p.pairing.clone(),
+            })
+            .collect()
+    }
+
+    pub fn lobby_message(&self) -> ServerMessage {
+        ServerMessage::Lobby {
+            to: None,
+            host: self.host,
+            rules: self.rules,
+            seats: self.seats(),
+        }
+    }
+
+    /// Make the deals and the robots' random choices reproducible.
+    pub fn seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        self.rng = StdRng::seed_from_u64(seed);
+        for p in &mut self.players {
+            p.seed(self.rng.gen());
+        }
+    }
+
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = rules;
+        for p in &mut self.players {
+            p.rules = rules;
+        }
+    }
+
+    pub fn add_robot(
+        &mut self,
+        strategy: Option<Strategy>,
+        difficulty: Difficulty,
+        name: Option<String>,
+    ) -> Result<ServerMessage> {
+        // a seat left to a stand-in is free between hands
+        let seat = match self.players.iter().position(|p| p.stand_in) {
+            Some(seat) if !self.is_playing() => seat,
+            _ if self.players.len() < Self::PLAYER_NUM as usize => self.players.len(),
+            _ => bail!("the table is full"),
+        };
+        let agent = self.robot(seat as u8, strategy, difficulty, name);
+        let msg = Self::robot_message(&agent);
+        if seat == self.players.len() {
+            self.players.push(agent);
+        } else {
+            self.players[seat] = agent;
+        }
+        Ok(msg)
     }
-    pub fn add_robot(&mut self) {
+
+    fn robot(
+        &self,
+        seat: u8,
+        strategy: Option<Strategy>,
+        difficulty: Difficulty,
+        name: Option<String>,
+    ) -> Agent {
         let mut agent = Agent::default();
         agent.is_robot = true;
         agent.ready = true;
-        agent.id = self.players.len() as u8;
+        agent.id = seat;
         agent.update_probability();
+        agent.difficulty = difficulty;
+        agent.rules = self.rules;
+        agent.weights = Weights::configured();
+        agent.name = match name {
+            Some(name) => name.trim().chars().take(Self::MAX_NAME).collect(),
+            None => format!("robot {}", agent.id),
+        };
         if self.test {
-            agent.strategy = Strategy::Test;
+            agent.set_strategy(Strategy::Test);
+        } else {
+            agent.set_strategy(strategy.unwrap_or(difficulty.strategy()));
+        }
+        agent
+    }
+
+    fn robot_message(agent: &Agent) -> ServerMessage {
+        ServerMessage::Robot {
+            to: None,
+            id: agent.id,
+            name: agent.name.clone(),
+            strategy: agent.strategy.clone(),
+            difficulty: agent.difficulty,
         }
-        self.players.push(agent)
     }
 
     pub fn start(&mut self) -> Result<()> {
-        if self.players.len() != 3 {
-            bail!("wrong players number {}", self.players.len());
+        if self.players.len() != 3 || self.players.iter().any(|p| p.stand_in) {
+            bail!("the table is not full");
+        }
+        if let Some(p) = self.players.iter().find(|p| !p.ready) {
+            bail!("{} is not ready", p.name);
         }
         self.winner = None;
+        self.winning_score = None;
+        self.owes_discard = false;
+        let seed = match self.seed {
+            Some(seed) => seed,
+            None => {
+                let seed = self.rng.gen();
+                self.seed(seed);
+                seed
+            }
+        };
+        self.seed = None;
         if !self.test {
             self.shuffle_cards();
-            self.jing = Card(rand::random::<u8>() % Self::TOTAL as u8);
-            self.turn = rand::random::<u8>() % 3;
+            self.jing = Card(self.rng.gen_range(0..Self::TOTAL as u8));
+            self.turn = self.rng.gen_range(0..Self::PLAYER_NUM);
             self.prev_turn = Some(self.turn);
         } else {
             self.remaining_cards.reverse();
@@ -196,20 +546,123 @@ impl GameState {
             self.players[i].jing = self.jing;
             self.players[i].update_probability();
         }
+        self.tell_wall();
+        self.replay = Some(Replay {
+            version: replay::VERSION,
+            seed,
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            room: self.room.clone(),
+            private: self.private,
+            rules: self.rules,
+            seats: self
+                .players
+                .iter()
+                .map(|p| Seat {
+                    name: p.name.clone(),
+                    strategy: p.is_robot.then(|| p.strategy.clone()),
+                })
+                .collect(),
+            jing: self.jing,
+            dealer: self.turn,
+            hands: self.players.iter().map(|p| p.hand.clone()).collect(),
+            wall: self.remaining_cards.clone(),
+            events: vec![],
+        });
         debug!("current turn {}", self.turn);
         Ok(())
     }
 
+    /// the hand being played, or the last one once it is over
+    pub fn replay(&self) -> Option<&Replay> {
+        self.replay.as_ref()
+    }
+
+    fn record(&mut self, event: Event) {
+        if self.is_over() {
+            return;
+        }
+        if let Some(replay) = &mut self.replay {
+            replay.events.push(event);
+        }
+    }
+
+    /// whether the hand ended and waits for the next `start`
+    pub fn is_over(&self) -> bool {
+        self.winner.is_some()
+    }
+
+    /// whether a hand was dealt and is not over
+    pub fn is_playing(&self) -> bool {
+        self.replay.is_some() && !self.is_over()
+    }
+
+    pub fn info(&self) -> RoomInfo {
+        let playing = self.is_playing();
+        RoomInfo {
+            id: self.room.clone(),
+            rules: self.rules,
+            seats: self.seats(),
+            playing,
+            turn: self.turn,
+            mode: self.mode,
+            wall: if playing {
+                self.remaining_cards.len()
+            } else {
+                0
+            },
+            jing: playing.then_some(self.jing),
+            reserved: 0,
+        }
+    }
+
     pub fn end(&mut self, even_flag: bool) {
         if !even_flag {
             self.winner = Some(self.turn);
+            let winner = &self.players[self.turn as usize];
+            self.winning_score = eval::evaluate(
+                &winner.hand,
+                &winner.pairing,
+                self.jing,
+                None,
+                self.rules.min_hu_score,
+            )
+            .map(|v| v.score);
         } else {
             self.winner = Some(u8::MAX);
         }
-        self.remaining_cards = (0..Self::TOTAL)
-            .into_iter()
-            .map(|n| Card(n as u8))
-            .collect();
+        if let Some(replay) = &mut self.replay {
+            replay.events.push(match self.winner {
+                Some(seat) if seat != u8::MAX => Event::Hu {
+                    seat,
+                    score: self.winning_score,
+                },
+                _ => Event::End,
+            });
+            let replay = replay.clone();
+            let store = self.replays.clone();
+            // the fixed test hands don't count
+            let stats = self.stats.clone().filter(|_| !self.test);
+            let results = self.results();
+            off_the_table(move || {
+                if let Some(store) = store {
+                    if let Err(e) = store.save(&replay) {
+                        warn!("failed to save the replay: {e:#}");
+                    }
+                }
+                if let Some(stats) = stats {
+                    if let Err(e) = stats.record(&results) {
+                        warn!("failed to record the stats: {e:#}");
+                    }
+                }
+            });
+        }
+        // the hands after this one don't count for whoever left
+        for p in self.players.iter_mut().filter(|p| p.stand_in) {
+            p.account = None;
+        }
+        self.remaining_cards = (0..Self::TOTAL).map(|n| Card(n as u8)).collect();
         self.turn = 0;
         self.round = 0;
         for i in 0..3 {
@@ -219,6 +672,74 @@ impl GameState {
         self.mode = Mode::Normal;
     }
 
+    /// how each seat did in the hand that just ended, read from its replay
+    /// before the hands are cleared
+    pub fn results(&self) -> Vec<SeatResult> {
+        let events = self.replay.as_ref().map_or(&[][..], |r| &r.events[..]);
+        let winner = self.winner.filter(|&w| w != u8::MAX);
+        // the winning card is drawn right before the Hu, after a Pao it is
+        // the one drawn in place of the fourth card
+        let mut last = events.iter().rev().skip(1);
+        let after_pao = matches!(
+            (last.next(), last.next()),
+            (Some(Event::Draw { .. }), Some(Event::Pao { seat, .. })) if Some(*seat) == winner
+        );
+        self.players
+            .iter()
+            .enumerate()
+            .map(|(i, player)| {
+                let seat = i as u8;
+                let won = winner == Some(seat);
+                let claims = |ding: bool| {
+                    events
+                        .iter()
+                        .filter(|e| match e {
+                            Event::Ding { seat: s, .. } => ding && *s == seat,
+                            Event::Pao { seat: s, .. } => !ding && *s == seat,
+                            _ => false,
+                        })
+                        .count() as u32
+                };
+                let jing = if won {
+                    let held = player.hand.iter().filter(|c| c.is_same_kind(&self.jing));
+                    let claimed = player.pairing.iter().map(|p| match p {
+                        Pairing::Triplet(c) if c.is_same_kind(&self.jing) => 3,
+                        Pairing::Quadlet(c) if c.is_same_kind(&self.jing) => 4,
+                        _ => 0,
+                    });
+                    held.count() as u32 + claimed.sum::<u32>()
+                } else {
+                    0
+                };
+                SeatResult {
+                    account: player.account.clone(),
+                    won,
+                    self_draw: won && !after_pao,
+                    score: self.winning_score.filter(|_| won),
+                    dings: claims(true),
+                    paos: claims(false),
+                    jing,
+                }
+            })
+            .collect()
+    }
+
+    /// Check that `seat` may discard `card` now: it is their turn, nothing
+    /// is offered to claim and they drew.
+    pub fn check_discard(&self, seat: u8, card: Card) -> Result<()> {
+        if !self.is_playing() || self.turn != seat || self.mode != Mode::Normal {
+            bail!("not your turn to discard");
+        }
+        let player = &self.players[seat as usize];
+        if player.card_count() != 20 {
+            bail!("draw before discarding");
+        }
+        if !player.hand.contains(&card) {
+            bail!("{card:?} is not in your hand");
+        }
+        Ok(())
+    }
+
     pub fn check_state(&self) {
         for p in &self.players {
             if p.id == self.turn {
@@ -229,65 +750,56 @@ impl GameState {
         }
     }
 
-    pub fn restore_turn(&mut self) -> ServerMessage {
-        self.turn = self.prev_turn.expect("missing previous turn");
+    /// the display name of `seat`
+    pub fn name(&self, seat: u8) -> String {
+        self.players[seat as usize].name.clone()
+    }
+
+    /// whose turn it is, in `mode`
+    pub fn turn_message(&self, mode: Mode) -> ServerMessage {
         ServerMessage::Turn {
             to: None,
             turn: self.turn,
-            mode: Mode::Normal,
+            name: self.name(self.turn),
+            mode,
+        }
+    }
+
+    /// the seat whose turn it is won
+    pub fn hu_message(&self) -> ServerMessage {
+        ServerMessage::Hu {
+            to: None,
+            seat: self.turn,
+            name: self.name(self.turn),
         }
     }
 
+    pub fn restore_turn(&mut self) -> ServerMessage {
+        self.turn = self.prev_turn.expect("missing previous turn");
+        self.turn_message(Mode::Normal)
+    }
+
     pub fn next_turn(&mut self, discard: &Card) -> ServerMessage {
-        let next_player = ((self.turn + 1) % Self::PLAYER_NUM) as usize;
-        let prev_player = ((next_player as u8 + 1) % Self::PLAYER_NUM) as usize;
-        self.prev_turn = Some((self.turn + 1) % Self::PLAYER_NUM);
-        if Self::can_form_quadlet(&self.players[next_player].hand, discard) {
-            self.turn = next_player as u8;
-            debug!("player {next_player} pao card {discard:?}");
-            self.mode = Mode::Pao(*discard);
-            ServerMessage::Turn {
-                to: None,
-                turn: self.turn,
-                mode: Mode::Pao(*discard),
-            }
-        } else if Self::can_form_quadlet(&self.players[prev_player].hand, discard) {
-            self.turn = prev_player as u8;
-            debug!("player {prev_player} pao card {discard:?}");
-            self.mode = Mode::Pao(*discard);
-            ServerMessage::Turn {
-                to: None,
-                turn: self.turn,
-                mode: Mode::Pao(*discard),
-            }
-        } else if Self::can_form_triplet(&self.players[next_player].hand, discard) {
-            self.turn = next_player as u8;
-            debug!("player {next_player} ding card {discard:?}");
-            self.mode = Mode::Ding(*discard);
-            ServerMessage::Turn {
-                to: None,
-                turn: self.turn,
-                mode: Mode::Ding(*discard),
-            }
-        } else if Self::can_form_triplet(&self.players[prev_player].hand, discard) {
-            self.turn = prev_player as u8;
-            debug!("player {prev_player} ding card {discard:?}");
-            self.mode = Mode::Ding(*discard);
-            ServerMessage::Turn {
-                to: None,
-                turn: self.turn,
-                mode: Mode::Ding(*discard),
+        self.prev_turn = Some(rules::next_seat(self.turn));
+        let claim = self.rules.claim(self.turn, *discard, |s| {
+            Self::count_same_type(&self.players[s as usize].hand, discard) as usize
+        });
+        match claim {
+            Some((seat, pairing)) => {
+                debug!("player {seat} may claim {pairing:?}");
+                self.turn = seat;
+                self.mode = match pairing {
+                    Pairing::Quadlet(card) => Mode::Pao(card),
+                    Pairing::Triplet(card) => Mode::Ding(card),
+                };
             }
-        } else {
-            debug!("[next_turn] self.turn {}", self.turn);
-            self.turn = (self.turn + 1) % Self::PLAYER_NUM;
-            self.mode = Mode::Normal;
-            ServerMessage::Turn {
-                to: None,
-                turn: self.turn,
-                mode: Mode::Normal,
+            None => {
+                debug!("[next_turn] self.turn {}", self.turn);
+                self.turn = rules::next_seat(self.turn);
+                self.mode = Mode::Normal;
             }
         }
+        self.turn_message(self.mode)
     }
 
     pub fn handle_ding_or_pao_out(&mut self, card: &Card) {
@@ -307,21 +819,49 @@ impl GameState {
                     self.players[i].player_left_out.pop();
                 }
             }
-            let mut index = vec![];
-            for (i, c) in self.players[i].hand.iter().enumerate() {
-                if c.is_same_kind(&card) {
-                    index.push(i);
-                }
+        }
+        // robots have already moved the claimed cards into their pairing,
+        // only a human claimer still holds them in hand
+        let turn = self.turn as usize;
+        if self.players[turn].is_robot {
+            return;
+        }
+        let pairing = match Self::count_same_type(&self.players[turn].hand, card) {
+            3 => Pairing::Quadlet(*card),
+            2 => Pairing::Triplet(*card),
+            _ => return,
+        };
+        self.players[turn].hand.retain(|c| !c.is_same_kind(card));
+        for i in 0..3 {
+            if i == turn || !self.players[i].is_robot {
+                continue;
             }
-            for i in 0..index.len() {
-                self.players[i].hand.remove(index[i] - i);
+            if (i + 1) % 3 == turn {
+                self.players[i].player_right_pairing.push(pairing);
+            } else {
+                self.players[i].player_left_pairing.push(pairing);
             }
         }
+        self.players[turn].pairing.push(pairing);
+    }
+
+    /// Let everyone know how many cards are left in the wall, as they can
+    /// see it on the table.
+    fn tell_wall(&mut self) {
+        let wall = self.remaining_cards.len() as u8;
+        for p in &mut self.players {
+            p.wall = Some(wall);
+        }
     }
 
     pub fn draw_card(&mut self) -> ServerMessage {
         if let Some(card) = self.remaining_cards.pop() {
+            self.record(Event::Draw {
+                seat: self.turn,
+                card,
+            });
             self.players[self.turn as usize].draw_card(card);
+            self.tell_wall();
             self.check_state();
             ServerMessage::Draw {
                 to: Some(self.turn),
@@ -333,14 +873,86 @@ impl GameState {
         }
     }
 
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Answer the Ding or Pao offered to the human whose turn it is, and
+    /// return the pairing if it is accepted.
+    pub fn answer_claim(&mut self, confirm: bool) -> Result<Option<Pairing>> {
+        let pairing = match self.mode {
+            Mode::Ding(card) => Pairing::Triplet(card),
+            Mode::Pao(card) => Pairing::Quadlet(card),
+            Mode::Normal => bail!("no claim to answer"),
+        };
+        self.mode = Mode::Normal;
+        let seat = self.turn;
+        if !confirm {
+            self.record(Event::Decline { seat, pairing });
+            self.restore_turn();
+            return Ok(None);
+        }
+        let card = match pairing {
+            Pairing::Triplet(card) => {
+                self.record(Event::Ding { seat, card });
+                card
+            }
+            Pairing::Quadlet(card) => {
+                self.record(Event::Pao { seat, card });
+                card
+            }
+        };
+        self.handle_ding_or_pao_out(&card);
+        Ok(Some(pairing))
+    }
+
+    /// What the player at `seat` can see of the game.
+    pub fn observe(&self, seat: usize) -> Observation {
+        let me = &self.players[seat];
+        let right = &self.players[(seat + 1) % 3];
+        let left = &self.players[(seat + 2) % 3];
+        let mut observation = Observation {
+            id: seat as u8,
+            hand: me.hand.clone(),
+            out: me.out.clone(),
+            pairing: me.pairing.clone(),
+            right_out: right.out.clone(),
+            right_pairing: right.pairing.clone(),
+            left_out: left.out.clone(),
+            left_pairing: left.pairing.clone(),
+            jing: self.jing,
+            unseen: 0,
+            wall: self.remaining_cards.len() as u8,
+        };
+        observation.unseen = observation.count_unseen();
+        observation
+    }
+
     pub fn is_robot_turn(&self) -> bool {
         self.players[self.turn as usize].is_robot
     }
 
-    pub fn robot_turn(&mut self, con: Option<&Sender<ServerMessage>>) -> Option<Card> {
+    /// Play the robot on turn, asking its external bot in place. Returns
+    /// the card it discards, or `None` when its turn ended otherwise.
+    pub fn robot_turn(&mut self, con: Option<&Outbox>) -> Option<Card> {
+        loop {
+            match self.robot_step(con) {
+                RobotStep::Played(card) => return card,
+                RobotStep::Ask(question) => {
+                    let agent = &mut self.players[self.turn as usize];
+                    if let Some((mut bot, observation)) = agent.lend_bot() {
+                        let answer = bot.answer(question, &observation);
+                        agent.answered(bot, answer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Play the robot on turn up to the first decision its external bot
+    /// has not answered yet. Calling it again resumes the turn.
+    pub fn robot_step(&mut self, con: Option<&Outbox>) -> RobotStep {
         assert!(self.is_robot_turn());
-        #[allow(unused)]
-        let mut card = Card(0);
         let right = (self.turn + 1) % Self::PLAYER_NUM;
         let left = (right + 1) % Self::PLAYER_NUM;
 
@@ -354,127 +966,136 @@ impl GameState {
             self.mode, self.turn
         );
 
-        match self.mode {
-            Mode::Pao(discard) => {
-                if self.players[self.turn as usize].pao_card(discard) {
-                    for (pos, _) in [(Pos::Right, right), (Pos::Left, left)] {
-                        let is_robot = match pos {
-                            Pos::Right => {
-                                if self.players[right as usize].is_robot {
-                                    self.players[right as usize]
-                                        .player_left_pairing
-                                        .push(Pairing::Quadlet(discard));
-                                    true
-                                } else {
-                                    false
-                                }
-                            }
-                            Pos::Left => {
-                                if self.players[left as usize].is_robot {
-                                    self.players[left as usize]
-                                        .player_right_pairing
-                                        .push(Pairing::Quadlet(discard));
-                                    true
-                                } else {
-                                    false
-                                }
+        if !self.owes_discard {
+            let claim = match self.mode {
+                Mode::Pao(card) => Some(Pairing::Quadlet(card)),
+                Mode::Ding(card) => Some(Pairing::Triplet(card)),
+                Mode::Normal => None,
+            };
+            if let Some(claim) = claim {
+                if self.players[self.turn as usize].needs_answer() {
+                    return RobotStep::Ask(Question::Claim(claim));
+                }
+            }
+            match self.mode {
+                Mode::Pao(discard) => {
+                    if self.players[self.turn as usize].pao_card(discard) {
+                        self.record(Event::Pao {
+                            seat: self.turn,
+                            card: discard,
+                        });
+                        if self.players[right as usize].is_robot {
+                            self.players[right as usize]
+                                .player_left_pairing
+                                .push(Pairing::Quadlet(discard));
+                        }
+                        if self.players[left as usize].is_robot {
+                            self.players[left as usize]
+                                .player_right_pairing
+                                .push(Pairing::Quadlet(discard));
+                        }
+                        if let Some(con) = con {
+                            con.send(ServerMessage::Pao {
+                                to: None,
+                                seat: self.turn,
+                                name: self.name(self.turn),
+                                card: discard,
+                            });
+                        }
+                        self.handle_ding_or_pao_out(&discard);
+                        let msg = self.draw_card();
+                        if let ServerMessage::End { .. } = msg {
+                            if let Some(con) = con {
+                                con.send(msg);
                             }
-                        };
-                        if !is_robot {
+                            return RobotStep::Played(None);
+                        }
+                        if self.is_player_hu() {
                             if let Some(con) = con {
-                                con.send(ServerMessage::Pao {
-                                    to: None,
-                                    card: discard,
-                                })
-                                .ok();
+                                con.send(self.hu_message());
                             }
+                            self.end(false);
+                            return RobotStep::Played(None);
+                        }
+                    } else {
+                        self.record(Event::Decline {
+                            seat: self.turn,
+                            pairing: Pairing::Quadlet(discard),
+                        });
+                        self.mode = Mode::Normal;
+                        let msg = self.restore_turn();
+                        if let Some(con) = con {
+                            con.send(msg);
+                        }
+                        return RobotStep::Played(None);
+                    }
+                }
+                Mode::Ding(discard) => {
+                    if self.players[self.turn as usize].ding_card(discard) {
+                        self.record(Event::Ding {
+                            seat: self.turn,
+                            card: discard,
+                        });
+                        if self.players[right as usize].is_robot {
+                            self.players[right as usize]
+                                .player_left_pairing
+                                .push(Pairing::Triplet(discard));
                         }
+                        if self.players[left as usize].is_robot {
+                            self.players[left as usize]
+                                .player_right_pairing
+                                .push(Pairing::Triplet(discard));
+                        }
+                        if let Some(con) = con {
+                            con.send(ServerMessage::Ding {
+                                to: None,
+                                seat: self.turn,
+                                name: self.name(self.turn),
+                                card: discard,
+                            });
+                        }
+                        self.handle_ding_or_pao_out(&discard);
+                    } else {
+                        self.record(Event::Decline {
+                            seat: self.turn,
+                            pairing: Pairing::Triplet(discard),
+                        });
+                        self.mode = Mode::Normal;
+                        let msg = self.restore_turn();
+                        if let Some(con) = con {
+                            con.send(msg);
+                        }
+                        return RobotStep::Played(None);
                     }
-                    self.handle_ding_or_pao_out(&discard);
+                }
+                Mode::Normal => {
                     let msg = self.draw_card();
-                    match msg {
-                        ServerMessage::End { .. } => return None,
-                        _ => {}
+                    if let ServerMessage::End { .. } = msg {
+                        if let Some(con) = con {
+                            con.send(msg);
+                        }
+                        return RobotStep::Played(None);
                     }
                     if self.is_player_hu() {
                         if let Some(con) = con {
-                            con.send(ServerMessage::Hu { to: None }).ok();
+                            con.send(self.hu_message());
                         }
                         self.end(false);
-                        return None;
-                    }
-                    card = self.players[self.turn as usize].discard_card();
-                } else {
-                    self.mode = Mode::Normal;
-                    let msg = self.restore_turn();
-                    if let Some(con) = con {
-                        con.send(msg).ok();
-                    }
-                    return None;
-                }
-            }
-            Mode::Ding(discard) => {
-                if self.players[self.turn as usize].ding_card(discard) {
-                    for (pos, _) in [(Pos::Right, right), (Pos::Left, left)] {
-                        let is_robot = match pos {
-                            Pos::Right => {
-                                if self.players[right as usize].is_robot {
-                                    self.players[right as usize]
-                                        .player_left_pairing
-                                        .push(Pairing::Triplet(discard));
-                                    true
-                                } else {
-                                    false
-                                }
-                            }
-                            Pos::Left => {
-                                if self.players[left as usize].is_robot {
-                                    self.players[left as usize]
-                                        .player_right_pairing
-                                        .push(Pairing::Triplet(discard));
-                                    true
-                                } else {
-                                    false
-                                }
-                            }
-                        };
-                        if !is_robot {
-                            if let Some(con) = con {
-                                con.send(ServerMessage::Ding {
-                                    to: None,
-                                    card: discard,
-                                })
-                                .ok();
-                            }
-                        }
-                    }
-                    self.handle_ding_or_pao_out(&discard);
-                    card = self.players[self.turn as usize].discard_card();
-                } else {
-                    self.mode = Mode::Normal;
-                    let msg = self.restore_turn();
-                    if let Some(con) = con {
-                        con.send(msg).ok();
-                    }
-                    return None;
-                }
-            }
-            Mode::Normal => {
-                let msg = self.draw_card();
-                match msg {
-                    ServerMessage::End { .. } => return None,
-                    _ => {}
-                }
-                if self.is_player_hu() {
-                    if let Some(con) = con {
-                        con.send(ServerMessage::Hu { to: None }).ok();
+                        return RobotStep::Played(None);
                     }
-                    self.end(false);
-                    return None;
                 }
-                card = self.players[self.turn as usize].discard_card();
             }
+            self.owes_discard = true;
         }
+        if self.players[self.turn as usize].needs_answer() {
+            return RobotStep::Ask(Question::Discard);
+        }
+        self.owes_discard = false;
+        let card = self.players[self.turn as usize].discard_card();
+        self.record(Event::Discard {
+            seat: self.turn,
+            card,
+        });
         for (pos, n) in [(Pos::Right, right), (Pos::Left, left)] {
             let player = &mut self.players[n as usize];
             if player.is_robot {
@@ -482,20 +1103,9 @@ impl GameState {
                     Pos::Right => player.player_left_out.push(card),
                     Pos::Left => player.player_right_out.push(card),
                 }
-            } else {
-                if let Some(con) = con {
-                    con.send(ServerMessage::Discard { to: Some(n), card }).ok();
-                }
             }
         }
-        Some(card)
-    }
-
-    fn can_form_quadlet(hand: &Vec<Card>, card: &Card) -> bool {
-        Self::count_same_type(hand, card) == 3
-    }
-    fn can_form_triplet(hand: &Vec<Card>, card: &Card) -> bool {
-        Self::count_same_type(hand, card) == 2
+        RobotStep::Played(Some(card))
     }
 
     fn count_same_type(hand: &Vec<Card>, card: &Card) -> u8 {
@@ -510,7 +1120,7 @@ impl GameState {
 
     fn shuffle_cards(&mut self) {
         for i in 0..96usize {
-            let j = rand::random::<usize>() % Self::TOTAL;
+            let j = self.rng.gen_range(0..Self::TOTAL);
             (self.remaining_cards[i], self.remaining_cards[j]) =
                 (self.remaining_cards[j], self.remaining_cards[i]);
         }
@@ -521,76 +1131,22 @@ impl GameState {
     }
 
     pub fn is_player_hu(&self) -> bool {
-        let mut score = 0;
-        for p in &self.players[self.turn as usize].pairing {
-            match p {
-                Pairing::Triplet(_) => score += 2,
-                Pairing::Quadlet(_) => score += 6,
-            }
-        }
-        Self::is_hu(&self.players[self.turn as usize].hand, score, self.jing)
+        let score = self.players[self.turn as usize]
+            .pairing
+            .iter()
+            .map(|p| p.score())
+            .sum();
+        Self::is_hu(
+            &self.players[self.turn as usize].hand,
+            score,
+            self.jing,
+            self.rules.min_hu_score,
+        )
     }
 
-    pub fn is_hu(hand: &Vec<Card>, mut score: u8, jing: Card) -> bool {
-        let mut hand_cnt = HashMap::new();
-        for c in hand {
-            hand_cnt.entry(c.0 / 4).and_modify(|e| *e += 1).or_insert(1);
-        }
-        // debug!("hand_cnt: {hand_cnt:?}");
-        fn minus_entry(cnt: &mut HashMap<u8, i32>, key: u8, val: i32) {
-            cnt.entry(key).and_modify(|e| *e -= val);
-            if cnt[&key] == 0 {
-                cnt.remove(&key);
-            }
-        }
-        for i in 0..8 {
-            let i = 3 * i;
-            let j = i + 1;
-            let k = i + 2;
-            while hand_cnt.contains_key(&i)
-                && hand_cnt.contains_key(&j)
-                && hand_cnt.contains_key(&k)
-            {
-                if i == 0 {
-                    score += 4;
-                }
-                for x in [i, j, k] {
-                    if jing.is_same_kind(&Card(x * 4)) {
-                        score += 4;
-                    }
-                }
-                minus_entry(&mut hand_cnt, i, 1);
-                minus_entry(&mut hand_cnt, j, 1);
-                minus_entry(&mut hand_cnt, k, 1);
-            }
-        }
-        // debug!("hand_cnt: {hand_cnt:?}, score: {score}");
-
-        for i in 0..24 {
-            if hand_cnt.contains_key(&i) {
-                if hand_cnt[&i] >= 3 {
-                    minus_entry(&mut hand_cnt, i, 3);
-                    score += 4;
-                    if i == 0 {
-                        score += 8;
-                    }
-                    if jing.is_same_kind(&Card(i * 4)) {
-                        score += 8;
-                    }
-                }
-            }
-        }
-        // debug!("hand_cnt: {hand_cnt:?}, score: {score}");
-
-        if hand_cnt.len() != 2 {
-            return false;
-        }
-        let keys: Vec<&u8> = hand_cnt.keys().collect();
-        if score >= 12 {
-            keys[0] / 3 == keys[1] / 3
-        } else {
-            false
-        }
+    /// `score` is the score of the pairings, which are not in `hand`
+    pub fn is_hu(hand: &[Card], score: u8, jing: Card, min_score: u8) -> bool {
+        eval::breakdown(hand, &[], jing).is_some_and(|b| score + b.score >= min_score)
     }
 
     pub fn discard_card(&mut self, player_id: usize, card: Card) -> Result<()> {
@@ -605,7 +1161,21 @@ impl GameState {
             index
         };
         if let Some(index) = index {
+            self.record(Event::Discard {
+                seat: player_id as u8,
+                card,
+            });
             self.players[player_id].hand.remove(index);
+            self.players[player_id].out.push(card);
+            // robots follow the discards of their neighbours, as in `robot_turn`
+            let right = &mut self.players[(player_id + 1) % 3];
+            if right.is_robot {
+                right.player_left_out.push(card);
+            }
+            let left = &mut self.players[(player_id + 2) % 3];
+            if left.is_robot {
+                left.player_right_out.push(card);
+            }
             Ok(())
         } else {
             bail!("cannot find card {card:?} in player {player_id}");
@@ -614,210 +1184,751 @@ impl GameState {
 }
 
 impl Game {
-    pub async fn on_connection(&self, socket: WebSocket) {
-        let id = self.count.fetch_add(1, Ordering::Relaxed);
-        self.state.write().add_player();
-        if let Err(e) = self.handle_connection(id, socket).await {
-            warn!("connection terminated because of {e}");
+    /// robots a room seats at most, the rest is left to humans
+    pub const MAX_ROBOTS: usize = 2;
+    /// how long robots think before they play, unless the server says
+    /// otherwise
+    pub const THINKING: Duration = Duration::from_millis(800);
+
+    /// A game in `room` seating the robots of `settings` and keeping its
+    /// hands in `replays`. Its table runs on a task of its own until the
+    /// game is closed or dropped.
+    pub fn new(
+        room: &str,
+        settings: &Settings,
+        replays: Arc<dyn ReplayStore>,
+        stats: Arc<Stats>,
+        thinking: Duration,
+    ) -> Result<Self> {
+        let mut state = GameState {
+            room: room.to_string(),
+            private: settings.is_private(),
+            replays: Some(replays),
+            stats: Some(stats),
+            ..Default::default()
+        };
+        Self::configure(&mut state, settings)?;
+        let outbox = Arc::new(Outbox::default());
+        let (table, info) = Table::new(state, outbox.clone(), thinking);
+        let (commands, rx) = mpsc::unbounded_channel();
+        tokio::spawn(table.run(rx));
+        Ok(Self {
+            commands,
+            outbox,
+            info,
+            spectators: broadcast::channel(16).0,
+            spectator_chat: Default::default(),
+            next_spectator: AtomicU64::new(0),
+        })
+    }
+
+    /// Seat the robots of a room being created.
+    fn configure(state: &mut GameState, settings: &Settings) -> Result<()> {
+        if settings.robots.len() > Self::MAX_ROBOTS {
+            bail!(
+                "at most {} robots, got {}",
+                Self::MAX_ROBOTS,
+                settings.robots.len()
+            );
+        }
+        state.set_rules(settings.rules);
+        for robot in &settings.robots {
+            Self::add_robot(
+                state,
+                robot.strategy.clone(),
+                robot.difficulty,
+                robot.name.clone(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reject the robots a room cannot seat: external bots run programs on
+    /// the server, so they are only played offline.
+    pub fn check_robot(strategy: Option<&Strategy>) -> Result<()> {
+        if let Some(Strategy::External(bot)) = strategy {
+            bail!("external bot {bot} cannot play in a room");
         }
-        self.state.write().players.remove(id as usize);
+        Ok(())
     }
 
-    async fn handle_connection(&self, id: u8, mut socket: WebSocket) -> Result<()> {
-        let mut rx = self.connection.subscribe();
+    /// Seat a robot, leaving a seat for at least one human.
+    fn add_robot(
+        state: &mut GameState,
+        strategy: Option<Strategy>,
+        difficulty: Difficulty,
+        name: Option<String>,
+    ) -> Result<ServerMessage> {
+        Self::check_robot(strategy.as_ref())?;
+        let robots = state
+            .players
+            .iter()
+            .filter(|p| p.is_robot && !p.stand_in)
+            .count();
+        if robots >= Self::MAX_ROBOTS {
+            bail!("at most {} robots", Self::MAX_ROBOTS);
+        }
+        state.add_robot(strategy, difficulty, name)
+    }
 
+    /// the room as the table last left it
+    pub fn info(&self) -> RoomInfo {
+        self.info.borrow().clone()
+    }
+
+    /// Hand `command` to the table and wait for it to be played.
+    async fn ask<T>(&self, command: impl FnOnce(Reply<T>) -> Command) -> Result<T> {
+        let (reply, answer) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .ok()
+            .context("the room is closed")?;
+        answer.await.ok().context("the room is closed")?
+    }
+
+    /// Let `name` watch the table over `socket`. They get what is sent to
+    /// every seat and talk with the other spectators only.
+    pub async fn spectate(&self, name: &str, socket: WebSocket) {
+        let key = format!(
+            "spectator {}",
+            self.next_spectator.fetch_add(1, Ordering::Relaxed)
+        );
+        let table = self.outbox.subscribe();
+        let chat = self.spectators.subscribe();
+        if let Err(e) = self.watch(&key, name, table, chat, socket).await {
+            warn!("spectator connection terminated because of {e}");
+        }
+    }
+
+    async fn watch(
+        &self,
+        key: &str,
+        name: &str,
+        mut table: broadcast::Receiver<ServerMessage>,
+        mut chat: broadcast::Receiver<ServerMessage>,
+        mut socket: WebSocket,
+    ) -> Result<()> {
+        let lines = self.spectator_chat.lock().history();
+        socket
+            .send(ServerMessage::ChatHistory { to: None, lines }.into())
+            .await?;
         loop {
             tokio::select! {
-                update = rx.recv() => {
-                    let update = update.unwrap();
-                    // debug!("[send message] {update:?}");
-                    if update.is_broadcast() || update.to().is_some() && update.to().unwrap() == id {
-                        socket.send(update.into()).await?;
+                update = table.recv() => {
+                    let update = match update {
+                        Result::Ok(update) => update,
+                        // skip what was missed, the snapshot has it all
+                        Err(RecvError::Lagged(_)) => ServerMessage::Resync {
+                            to: None,
+                            room: self.info(),
+                        },
+                        Err(RecvError::Closed) => break,
+                    };
+                    let closed = matches!(update, ServerMessage::Closed { .. });
+                    socket.send(update.into()).await?;
+                    if closed {
+                        socket.close().await.ok();
+                        break;
                     }
                 }
-                result = socket.next() => {
-                    match result {
-                        None => break,
-                        Some(message) => {
-                            self.handle_message(id, message?).await?;
+                line = chat.recv() => match line {
+                    Result::Ok(line) => socket.send(line.into()).await?,
+                    // lines said meanwhile are lost
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                message = socket.next() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    let Result::Ok(text) = message?.to_str().map(str::to_string) else {
+                        continue;
+                    };
+                    let said = match serde_json::from_str(&text)
+                        .context("failed to deserialize client message")?
+                    {
+                        ClientMessage::Chat { text } => Said::Text(text),
+                        ClientMessage::Emote(emote) => Said::Emote(emote),
+                        _ => bail!("spectators only chat"),
+                    };
+                    let line = ChatLine {
+                        name: name.to_string(),
+                        seat: None,
+                        said,
+                    };
+                    let said = self.spectator_chat.lock().say(key, &line, Instant::now());
+                    let msg = match said {
+                        Result::Ok(()) => {
+                            self.spectators
+                                .send(ServerMessage::Chat { to: None, line })
+                                .ok();
+                            continue;
                         }
-                    }
+                        Err(e) => ServerMessage::Refused {
+                            to: None,
+                            reason: e.to_string(),
+                        },
+                    };
+                    socket.send(msg.into()).await?;
                 }
             }
         }
         Ok(())
     }
 
-    fn wait_robot(&self) {
+    /// Hang up on everyone in the room.
+    pub fn close(&self) {
+        // a table that is gone hung up already
+        self.commands.send(Command::Close).ok();
+    }
+
+    /// Seat a human named `name` and tell the table, unless only the `keep`
+    /// seats reserved for others are free. The queue of the seat opens
+    /// before the news goes out, so the newcomer hears it too.
+    pub async fn join(
+        &self,
+        name: &str,
+        account: Option<&str>,
+        keep: usize,
+    ) -> Result<(u8, Inbox)> {
+        let name = name.to_string();
+        let account = account.map(str::to_string);
+        self.ask(|reply| Command::Join {
+            name,
+            account,
+            keep,
+            reply,
+        })
+        .await
+    }
+
+    /// Play seat `id` over `socket` until either side hangs up, then hand
+    /// the seat to a stand-in.
+    pub async fn serve(&self, id: u8, inbox: Inbox, socket: WebSocket) {
+        if let Err(e) = self.handle_connection(id, inbox, socket).await {
+            warn!("connection terminated because of {e}");
+        }
+        // a closed room has no seat to hand over
+        self.ask(|reply| Command::Leave { seat: id, reply })
+            .await
+            .ok();
+    }
+
+    async fn handle_connection(
+        &self,
+        id: u8,
+        mut inbox: Inbox,
+        mut socket: WebSocket,
+    ) -> Result<()> {
         loop {
-            let is_robot_turn = self.state.read().is_robot_turn();
-            debug!(
-                "is robot turn {is_robot_turn}, turn {}",
-                self.state.read().turn
-            );
-            if !is_robot_turn {
-                break;
+            tokio::select! {
+                update = inbox.recv() => {
+                    // the queue closes when the seat fell too far behind
+                    let Some(update) = update else {
+                        warn!("seat {id} fell behind, hanging up");
+                        socket.close().await.ok();
+                        break;
+                    };
+                    let closed = matches!(
+                        update,
+                        ServerMessage::Closed { .. } | ServerMessage::Kicked { .. }
+                    );
+                    socket.send(update.into()).await?;
+                    if closed {
+                        socket.close().await.ok();
+                        break;
+                    }
+                }
+                result = socket.next() => {
+                    let Some(message) = result else {
+                        break;
+                    };
+                    let message = message?;
+                    let Result::Ok(text) = message.to_str() else {
+                        continue;
+                    };
+                    let message = serde_json::from_str(text)
+                        .context("failed to deserialize client message")?;
+                    self.ask(|reply| Command::Play {
+                        seat: id,
+                        message,
+                        reply,
+                    })
+                    .await?;
+                }
             }
-            let card = self.state.write().robot_turn(Some(&self.connection));
-            let msg = if let Some(card) = card {
-                self.state.write().next_turn(&card)
-            } else {
-                ServerMessage::Turn {
-                    to: None,
-                    turn: self.state.read().turn,
-                    mode: Mode::Normal,
+        }
+        Ok(())
+    }
+}
+
+type Reply<T> = oneshot::Sender<Result<T>>;
+
+/// What the table of a room is asked to do, played in the order asked.
+enum Command {
+    Join {
+        name: String,
+        account: Option<String>,
+        /// the seats to leave free for the players they are reserved for
+        keep: usize,
+        reply: Reply<(u8, Inbox)>,
+    },
+    Play {
+        seat: u8,
+        message: ClientMessage,
+        reply: Reply<()>,
+    },
+    Leave {
+        seat: u8,
+        reply: Reply<()>,
+    },
+    Close,
+}
+
+/// The task owning the game of a room. It plays one command at a time and
+/// the robots in between, so the state needs no lock.
+struct Table {
+    state: GameState,
+    outbox: Arc<Outbox>,
+    info: watch::Sender<RoomInfo>,
+    chat: Chat,
+    /// how long a robot thinks before it plays
+    thinking: Duration,
+    /// when the robot on turn plays
+    robot_due: Option<time::Instant>,
+    /// the human on turn draws once the robots are done
+    draw_pending: bool,
+}
+
+impl Table {
+    fn new(
+        state: GameState,
+        outbox: Arc<Outbox>,
+        thinking: Duration,
+    ) -> (Self, watch::Receiver<RoomInfo>) {
+        let (info, rx) = watch::channel(state.info());
+        let table = Self {
+            state,
+            outbox,
+            info,
+            chat: Default::default(),
+            thinking,
+            robot_due: None,
+            draw_pending: false,
+        };
+        (table, rx)
+    }
+
+    /// Play the commands as they come and the robots when they are done
+    /// thinking, until the room is closed or nobody holds it anymore.
+    async fn run(mut self, mut commands: mpsc::UnboundedReceiver<Command>) {
+        loop {
+            let due = self.robot_due;
+            tokio::select! {
+                command = commands.recv() => {
+                    let Some(command) = command else {
+                        break;
+                    };
+                    if !self.handle(command) {
+                        break;
+                    }
+                }
+                _ = time::sleep_until(due.unwrap_or_else(time::Instant::now)), if due.is_some() => {
+                    self.robot_due = None;
+                    self.robot_move().await;
+                    self.settle();
                 }
+            }
+        }
+    }
+
+    /// Play `command` and answer it once the room shows its outcome.
+    /// Returns whether the table goes on.
+    fn handle(&mut self, command: Command) -> bool {
+        match command {
+            Command::Join {
+                name,
+                account,
+                keep,
+                reply,
+            } => {
+                let joined = self.join(&name, account, keep);
+                self.settle();
+                reply.send(joined).ok();
+            }
+            Command::Play {
+                seat,
+                message,
+                reply,
+            } => {
+                let played = self.play(seat, message);
+                self.settle();
+                reply.send(played).ok();
+            }
+            Command::Leave { seat, reply } => {
+                self.leave(seat);
+                self.settle();
+                reply.send(Ok(())).ok();
+            }
+            Command::Close => {
+                self.outbox.send(ServerMessage::Closed { to: None });
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Let the robot on turn think, or draw for the human on turn once the
+    /// robots are done, and show the room as it is now.
+    fn settle(&mut self) {
+        if !self.state.is_playing() {
+            self.robot_due = None;
+            self.draw_pending = false;
+        } else if self.state.is_robot_turn() {
+            let thinking = self.thinking;
+            self.robot_due
+                .get_or_insert_with(|| time::Instant::now() + thinking);
+        } else {
+            self.robot_due = None;
+            if std::mem::take(&mut self.draw_pending) {
+                self.player_draw();
+            }
+        }
+        self.info.send_replace(self.state.info());
+    }
+
+    /// Play the robot on turn and pass the turn on.
+    async fn robot_move(&mut self) {
+        if !self.state.is_playing() || !self.state.is_robot_turn() {
+            return;
+        }
+        let card = self.robot_turn().await;
+        if !self.state.is_over() {
+            self.pass_turn(card);
+        }
+    }
+
+    /// Play the robot on turn, asking its external bot on a blocking
+    /// thread so the other rooms go on meanwhile.
+    async fn robot_turn(&mut self) -> Option<Card> {
+        loop {
+            let question = match self.state.robot_step(Some(&self.outbox)) {
+                RobotStep::Played(card) => return card,
+                RobotStep::Ask(question) => question,
+            };
+            let seat = self.state.turn as usize;
+            // without its bot the robot plays its fallback
+            let Some((mut bot, observation)) = self.state.players[seat].lend_bot() else {
+                continue;
             };
-            self.connection.send(msg).ok();
+            let (bot, answer) = tokio::task::spawn_blocking(move || {
+                let answer = bot.answer(question, &observation);
+                (bot, answer)
+            })
+            .await
+            .expect("external bot thread panicked");
+            self.state.players[seat].answered(bot, answer);
         }
     }
 
-    async fn handle_message(&self, id: u8, message: Message) -> Result<()> {
-        let message: ClientMessage = match message.to_str() {
-            Err(()) => return Ok(()),
-            ::std::result::Result::Ok(text) => {
-                serde_json::from_str(text).context("failed to deserialized client message")?
+    /// Tell the table `card` was discarded and pass the turn on, or give
+    /// it back when a turn ended without a discard.
+    fn pass_turn(&mut self, card: Option<Card>) {
+        let msg = match card {
+            Some(card) => {
+                let seat = self.state.turn;
+                self.outbox.send(ServerMessage::Discard {
+                    to: None,
+                    seat,
+                    name: self.state.name(seat),
+                    card,
+                });
+                self.state.next_turn(&card)
             }
+            None => self.state.turn_message(Mode::Normal),
+        };
+        self.outbox.send(msg);
+        self.draw_pending = true;
+    }
+
+    fn send_lobby(&self) {
+        self.outbox.send(self.state.lobby_message());
+    }
+
+    fn host_only(&self, id: u8) -> Result<()> {
+        if self.state.host != Some(id) {
+            bail!("only the host can do that");
+        }
+        Ok(())
+    }
+
+    /// Tell seat `id` why what it asked for was not done.
+    fn refuse(&self, id: u8, reason: anyhow::Error) {
+        self.outbox.send(ServerMessage::Refused {
+            to: Some(id),
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Pass on what seat `id` said to the table.
+    fn say(&mut self, id: u8, said: Said) {
+        let line = ChatLine {
+            name: self.state.name(id),
+            seat: Some(id),
+            said,
         };
-        // debug!("[handle message] message {message:?}");
+        match self.chat.say(&format!("seat {id}"), &line, Instant::now()) {
+            Result::Ok(()) => self.outbox.send(ServerMessage::Chat { to: None, line }),
+            Err(e) => self.refuse(id, e),
+        }
+    }
+
+    fn join(&mut self, name: &str, account: Option<String>, keep: usize) -> Result<(u8, Inbox)> {
+        if self.state.info().free_seats() <= keep {
+            bail!("the seats left are reserved");
+        }
+        let name: String = name.trim().chars().take(GameState::MAX_NAME).collect();
+        let id = self.state.add_player()?;
+        let player = &mut self.state.players[id as usize];
+        player.name = name.clone();
+        player.account = account;
+        let inbox = self.outbox.open(id);
+        self.outbox
+            .send(ServerMessage::Joined { to: None, id, name });
+        self.send_lobby();
+        self.outbox.send(ServerMessage::ChatHistory {
+            to: Some(id),
+            lines: self.chat.history(),
+        });
+        Ok((id, inbox))
+    }
+
+    /// Hand the seat of a human who left to a stand-in, which plays in turn
+    /// like any robot.
+    fn leave(&mut self, id: u8) {
+        let msg = self.state.leave(id);
+        self.outbox.close(id);
+        self.outbox.send(msg);
+        self.send_lobby();
+        if self.state.is_playing() && self.state.is_robot_turn() {
+            self.draw_pending = true;
+        }
+    }
+
+    fn play(&mut self, id: u8, message: ClientMessage) -> Result<()> {
         match message {
+            ClientMessage::Hello { .. } => bail!("already said hello"),
+            #[cfg(test)]
             ClientMessage::Test(_) => {
-                self.state.write().test = true;
+                self.state.test = true;
             }
-            ClientMessage::Ready(_) => {
-                self.state.write().players[id as usize].ready = true;
+            ClientMessage::Chat { text } => self.say(id, Said::Text(text)),
+            ClientMessage::Emote(emote) => self.say(id, Said::Emote(emote)),
+            ClientMessage::Ready(ready) => {
+                self.state.players[id as usize].ready = ready;
+                self.send_lobby();
             }
-            ClientMessage::AddRobot(_) => {
-                self.state.write().add_robot();
+            ClientMessage::AddRobot {
+                strategy,
+                difficulty,
+                name,
+            } => {
+                let added = self
+                    .host_only(id)
+                    .and_then(|_| Game::add_robot(&mut self.state, strategy, difficulty, name));
+                match added {
+                    Result::Ok(msg) => {
+                        self.outbox.send(msg);
+                        self.send_lobby();
+                    }
+                    Err(e) => self.refuse(id, e),
+                }
             }
-            ClientMessage::Start(_) => {
-                self.state.write().start()?;
-                {
-                    let state = self.state.read();
-                    for i in 0..3 {
-                        self.connection
-                            .send(ServerMessage::Initial {
-                                to: Some(i),
-                                cur_turn: state.turn,
-                                hand: state.hand_of_player(i as usize),
-                                jing: state.jing,
-                            })
-                            .ok();
+            ClientMessage::RemoveRobot { seat } => {
+                let removed = self
+                    .host_only(id)
+                    .and_then(|_| self.state.remove_robot(seat));
+                match removed {
+                    Result::Ok(msg) => {
+                        self.outbox.send(msg);
+                        self.send_lobby();
                     }
+                    Err(e) => self.refuse(id, e),
                 }
-                self.wait_robot();
-
-                if Mode::Normal == self.state.read().mode {
-                    let msg = self.state.write().draw_card();
-                    debug!("write draw card message success");
-                    self.connection.send(msg).ok();
-                    let is_hu = self.state.read().is_player_hu();
-                    if is_hu {
-                        self.connection.send(ServerMessage::Hu { to: None }).ok();
-                        self.state.write().end(false);
+            }
+            ClientMessage::SetRules(rules) => {
+                let set = self.host_only(id).and_then(|_| {
+                    if self.state.is_playing() {
+                        bail!("the rules change between hands");
                     }
+                    self.state.set_rules(rules);
+                    Ok(())
+                });
+                match set {
+                    Result::Ok(()) => self.send_lobby(),
+                    Err(e) => self.refuse(id, e),
                 }
             }
-            ClientMessage::Discard { card } => {
-                self.state.write().discard_card(id as usize, card)?;
-                let mut card = Some(card);
-                loop {
-                    debug!("next turn, discard card {:?}", card);
-                    let msg = if let Some(card) = card {
-                        self.state.write().next_turn(&card)
-                    } else {
-                        ServerMessage::Turn {
-                            to: None,
-                            turn: self.state.read().turn,
-                            mode: Mode::Normal,
-                        }
-                    };
-                    debug!("next turn, msg {:?}", msg);
-                    self.connection.send(msg).ok();
-                    if !self.state.read().is_robot_turn() {
-                        break;
+            ClientMessage::Kick { seat } => {
+                let kick = self.host_only(id).and_then(|_| {
+                    if seat == id || !self.state.is_human(seat) {
+                        bail!("no one else to send away at seat {seat}");
+                    }
+                    Ok(())
+                });
+                match kick {
+                    // their connection hangs up and leaves the seat to a stand-in
+                    Result::Ok(()) => {
+                        self.outbox.send(ServerMessage::Kicked { to: Some(seat) });
                     }
-                    card = self.state.write().robot_turn(Some(&self.connection));
+                    Err(e) => self.refuse(id, e),
                 }
-
-                if Mode::Normal == self.state.read().mode {
-                    let msg = self.state.write().draw_card();
-                    self.connection.send(msg).ok();
-                    let is_hu = self.state.read().is_player_hu();
-                    if is_hu {
-                        self.connection.send(ServerMessage::Hu { to: None }).ok();
-                        self.state.write().end(false);
+            }
+            ClientMessage::Start(_) => {
+                let started = self.host_only(id).and_then(|_| {
+                    if self.state.is_playing() {
+                        bail!("a hand is being played");
                     }
+                    self.state.start()
+                });
+                if let Err(e) = started {
+                    self.refuse(id, e);
+                    return Ok(());
+                }
+                for i in 0..3 {
+                    self.outbox.send(ServerMessage::Initial {
+                        to: Some(i),
+                        cur_turn: self.state.turn,
+                        hand: self.state.hand_of_player(i as usize),
+                        jing: self.state.jing,
+                        names: self.state.players.iter().map(|p| p.name.clone()).collect(),
+                    });
+                }
+                self.draw_pending = true;
+            }
+            ClientMessage::Discard { card } => {
+                let discarded = self
+                    .state
+                    .check_discard(id, card)
+                    .and_then(|_| self.state.discard_card(id as usize, card));
+                match discarded {
+                    Result::Ok(()) => self.pass_turn(Some(card)),
+                    Err(e) => self.refuse(id, e),
                 }
             }
             ClientMessage::Ding { confirm } => {
-                let mode = self.state.read().mode.clone();
-                self.state.write().mode = Mode::Normal;
-                if !confirm {
-                    let msg = self.state.write().restore_turn();
-                    self.connection.send(msg).ok();
-                    self.wait_robot();
+                let mode = self.state.mode;
+                if !matches!(mode, Mode::Ding(_)) {
+                    bail!("wrong mode, expect Ding mode, got {:?}", mode);
+                }
+                let claim = self.state.answer_claim(confirm)?;
+                if let Some(Pairing::Triplet(card)) = claim {
+                    self.outbox.send(ServerMessage::Ding {
+                        to: None,
+                        seat: id,
+                        name: self.state.name(id),
+                        card,
+                    });
                 } else {
-                    let card = match mode {
-                        Mode::Ding(c) => c,
-                        _ => bail!("wrong mode, expect Ding mode, got {:?}", mode),
-                    };
-                    self.state.write().handle_ding_or_pao_out(&card);
-                    let msg = ServerMessage::Ding { to: None, card };
-                    self.connection.send(msg).ok();
+                    self.pass_turn(None);
                 }
             }
             ClientMessage::Pao { confirm } => {
-                let mode = self.state.read().mode.clone();
-                self.state.write().mode = Mode::Normal;
-                if !confirm {
-                    let msg = self.state.write().restore_turn();
-                    self.connection.send(msg).ok();
-                    self.wait_robot();
-                } else {
-                    let card = match mode {
-                        Mode::Pao(c) => c,
-                        _ => bail!("wrong mode, expect Wa mode, got {:?}", mode),
-                    };
-                    self.state.write().handle_ding_or_pao_out(&card);
-                    let msg = ServerMessage::Pao { to: None, card };
-                    self.connection.send(msg).ok();
+                let mode = self.state.mode;
+                if !matches!(mode, Mode::Pao(_)) {
+                    bail!("wrong mode, expect Pao mode, got {:?}", mode);
                 }
-                let msg = self.state.write().draw_card();
-                self.connection.send(msg).ok();
-                let is_hu = self.state.read().is_player_hu();
-                if is_hu {
-                    self.connection.send(ServerMessage::Hu { to: None }).ok();
-                    self.state.write().end(false);
+                let claim = self.state.answer_claim(confirm)?;
+                if let Some(Pairing::Quadlet(card)) = claim {
+                    self.outbox.send(ServerMessage::Pao {
+                        to: None,
+                        seat: id,
+                        name: self.state.name(id),
+                        card,
+                    });
+                    // a Pao draws a replacement
+                    self.draw_pending = true;
+                } else {
+                    self.pass_turn(None);
                 }
             }
         }
         Ok(())
     }
+
+    /// Draw for the human whose turn it is, unless the hand is over or a
+    /// robot's discard offered them a claim.
+    fn player_draw(&mut self) {
+        if self.state.is_over() || self.state.mode != Mode::Normal {
+            return;
+        }
+        let msg = self.state.draw_card();
+        self.outbox.send(msg);
+        if self.state.is_player_hu() {
+            self.outbox.send(self.state.hu_message());
+            self.state.end(false);
+        }
+    }
+}
+
+/// Run the file writes of `work` on a blocking thread when a runtime runs
+/// the tables, and right away in the simulations and tests without one.
+fn off_the_table(work: impl FnOnce() + Send + 'static) {
+    match tokio::runtime::Handle::try_current() {
+        Result::Ok(runtime) => {
+            runtime.spawn_blocking(work);
+        }
+        Err(_) => work(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use warp::{test::WsClient, Filter};
-
-    use crate::{handler::socket_handler, GlobalState};
+    use crate::{
+        client::{Bot, Client},
+        eval::MIN_HU_SCORE,
+        external::{Answer, BotConfig, ExternalBot},
+        handler::routes,
+        history::MemoryStore,
+        room::RobotSettings,
+        GlobalState,
+    };
 
     use super::*;
 
-    struct JsonWsClient(WsClient);
-    impl JsonWsClient {
-        pub async fn send(&mut self, msg: ClientMessage) {
-            self.0.send_text(serde_json::to_string(&msg).unwrap()).await
+    impl Client {
+        /// the next message, past the lobby updates and the chat history
+        /// which are checked on their own
+        async fn next(&mut self) -> ServerMessage {
+            loop {
+                match self.recv().await.unwrap().expect("connection closed") {
+                    ServerMessage::Lobby { .. } | ServerMessage::ChatHistory { .. } => {}
+                    msg => return msg,
+                }
+            }
         }
-        pub async fn recv(&mut self) -> ServerMessage {
-            let s_msg = self.0.recv().await.unwrap();
-            let s_msg = s_msg.to_str().unwrap();
-            let s_msg: ServerMessage = serde_json::from_str(s_msg).unwrap();
-            s_msg
+
+        fn name(seat: u8) -> String {
+            match seat {
+                0 => "alice".to_string(),
+                n => format!("robot {n}"),
+            }
+        }
+
+        pub async fn expect_joined(&mut self, expect_id: u8, expect_name: &str) {
+            let msg = self.next().await;
+            match msg {
+                ServerMessage::Joined { to, id, name } => {
+                    assert!(to.is_none());
+                    assert_eq!(id, expect_id);
+                    assert_eq!(name, expect_name);
+                }
+                _ => panic!("expect joined message, got {msg:?}"),
+            }
         }
 
         pub async fn expect_draw(&mut self, expect_card: Card) {
-            let msg = self.recv().await;
+            let msg = self.next().await;
             match msg {
                 ServerMessage::Draw { to, card } => {
                     assert_eq!(to.unwrap(), 0);
@@ -827,56 +1938,95 @@ mod tests {
             }
         }
         pub async fn expect_turn(&mut self, expect_turn: u8, expect_mode: Mode) {
-            let msg = self.recv().await;
+            let msg = self.next().await;
             match msg {
-                ServerMessage::Turn { to, turn, mode } => {
-                    assert_eq!(to.is_none(), true);
+                ServerMessage::Turn {
+                    to,
+                    turn,
+                    name,
+                    mode,
+                } => {
+                    assert!(to.is_none());
                     assert_eq!(turn, expect_turn);
+                    assert_eq!(name, Self::name(turn));
                     assert_eq!(mode, expect_mode);
                 }
                 _ => panic!("expect turn message, got {msg:?}"),
             }
         }
         pub async fn expect_discard(&mut self, expect_card: Card) {
-            let msg = self.recv().await;
+            let msg = self.next().await;
             match msg {
-                ServerMessage::Discard { to, card } => {
-                    assert_eq!(to.unwrap(), 0);
+                ServerMessage::Discard {
+                    to,
+                    seat,
+                    name,
+                    card,
+                } => {
+                    assert!(to.is_none());
+                    assert_eq!(name, Self::name(seat));
                     assert_eq!(card, expect_card);
                 }
                 _ => panic!("expect discard message, got {msg:?}"),
             }
         }
         pub async fn expect_ding(&mut self, expect_card: Card) {
-            let msg = self.recv().await;
+            let msg = self.next().await;
             match msg {
-                ServerMessage::Ding { to, card } => {
+                ServerMessage::Ding {
+                    to,
+                    seat,
+                    name,
+                    card,
+                } => {
                     assert!(to.is_none());
+                    assert_eq!(name, Self::name(seat));
                     assert_eq!(card, expect_card);
                 }
                 _ => panic!("expect ding message, got {msg:?}"),
             }
         }
         pub async fn expect_pao(&mut self, expect_card: Card) {
-            let msg = self.recv().await;
+            let msg = self.next().await;
             match msg {
-                ServerMessage::Pao { to, card } => {
+                ServerMessage::Pao {
+                    to,
+                    seat,
+                    name,
+                    card,
+                } => {
                     assert!(to.is_none());
+                    assert_eq!(name, Self::name(seat));
                     assert_eq!(card, expect_card);
                 }
                 _ => panic!("expect pao message, got {msg:?}"),
             }
         }
+        pub async fn expect_robot(&mut self, expect_id: u8) {
+            let msg = self.next().await;
+            match msg {
+                ServerMessage::Robot {
+                    to, id, strategy, ..
+                } => {
+                    assert!(to.is_none());
+                    assert_eq!(id, expect_id);
+                    assert_eq!(strategy, Strategy::Test);
+                }
+                _ => panic!("expect robot message, got {msg:?}"),
+            }
+        }
         pub async fn expect_initial(&mut self, expect_turn: u8, expect_hand: &Vec<Card>) {
-            let msg = self.recv().await;
+            let msg = self.next().await;
             match msg {
                 ServerMessage::Initial {
                     to,
                     cur_turn,
                     hand,
                     jing,
+                    names,
                 } => {
                     assert_eq!(to.unwrap(), 0);
+                    assert_eq!(names, ["alice", "robot 1", "robot 2"]);
                     assert_eq!(cur_turn, expect_turn);
                     assert_eq!(&hand, expect_hand);
                     assert_eq!(jing.0, 95);
@@ -886,43 +2036,277 @@ mod tests {
         }
     }
 
-    async fn connect() -> JsonWsClient {
-        let w = warp::path!("api" / "ws" / String)
-            .and(warp::ws())
-            .and(warp::any().map(move || GlobalState::new()))
-            .and_then(socket_handler);
-        let client = warp::test::ws()
-            .path(&format!("/api/ws/{}", "test"))
-            .handshake(w)
+    /// the url of the room `test` on a fresh server
+    fn serve() -> String {
+        let (addr, server) = warp::serve(routes(GlobalState::new().with_thinking(Duration::ZERO)))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("ws://{addr}/api/ws/test")
+    }
+
+    async fn connect() -> Client {
+        let mut client = Client::join(&serve(), "alice").await.unwrap();
+        client.expect_joined(0, "alice").await;
+        client
+    }
+
+    #[tokio::test]
+    async fn hello_comes_first() {
+        let url = serve();
+        let mut client = Client::connect(&url).await.unwrap();
+        client.send(ClientMessage::Ready(true)).await.unwrap();
+        // hung up on without taking a seat
+        assert!(!matches!(client.recv().await, Result::Ok(Some(_))));
+        let mut client = Client::join(&url, "alice").await.unwrap();
+        client.expect_joined(0, "alice").await;
+        client
+            .send(ClientMessage::Hello {
+                name: "alice".to_string(),
+                user: None,
+                reservation: None,
+                spectate: false,
+            })
             .await
             .unwrap();
-        JsonWsClient(client)
+        loop {
+            match client.recv().await {
+                Result::Ok(Some(
+                    ServerMessage::Lobby { .. } | ServerMessage::ChatHistory { .. },
+                )) => {}
+                msg => {
+                    assert!(!matches!(msg, Result::Ok(Some(_))), "{msg:?}");
+                    break;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn names_reach_the_table() {
+        let url = serve();
+        let mut alice = Client::join(&url, "alice").await.unwrap();
+        alice.expect_joined(0, "alice").await;
+        let mut bob = Client::join(&url, "bob").await.unwrap();
+        bob.expect_joined(1, "bob").await;
+        alice.expect_joined(1, "bob").await;
+        alice
+            .send(ClientMessage::AddRobot {
+                strategy: Some(Strategy::Random),
+                difficulty: Difficulty::Normal,
+                name: Some("carol".to_string()),
+            })
+            .await
+            .unwrap();
+        let msg = alice.next().await;
+        assert!(matches!(msg, ServerMessage::Robot { id: 2, .. }), "{msg:?}");
+        alice.send(ClientMessage::Ready(true)).await.unwrap();
+        bob.send(ClientMessage::Ready(true)).await.unwrap();
+        alice.send(ClientMessage::Start(true)).await.unwrap();
+        loop {
+            match bob.next().await {
+                ServerMessage::Initial { to, names, .. } => {
+                    assert_eq!(to, Some(1));
+                    assert_eq!(names, ["alice", "bob", "carol"]);
+                    break;
+                }
+                ServerMessage::Robot { .. } => {}
+                msg => panic!("expect initial message, got {msg:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_host_runs_the_room() {
+        let url = serve();
+        let mut alice = Client::join(&url, "alice").await.unwrap();
+        alice.expect_joined(0, "alice").await;
+        let mut bob = Client::join(&url, "bob").await.unwrap();
+        bob.expect_joined(1, "bob").await;
+        alice.expect_joined(1, "bob").await;
+        bob.send(ClientMessage::AddRobot {
+            strategy: Some(Strategy::Random),
+            difficulty: Difficulty::Normal,
+            name: None,
+        })
+        .await
+        .unwrap();
+        assert!(matches!(bob.next().await, ServerMessage::Refused { .. }));
+        alice
+            .send(ClientMessage::AddRobot {
+                strategy: Some(Strategy::Random),
+                difficulty: Difficulty::Normal,
+                name: None,
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            alice.next().await,
+            ServerMessage::Robot { id: 2, .. }
+        ));
+        // bob is not ready yet
+        alice.send(ClientMessage::Ready(true)).await.unwrap();
+        alice.send(ClientMessage::Start(true)).await.unwrap();
+        match alice.next().await {
+            ServerMessage::Refused { reason, .. } => assert_eq!(reason, "bob is not ready"),
+            msg => panic!("expect refused, got {msg:?}"),
+        }
+        // the lobby tells everyone who is ready
+        bob.send(ClientMessage::Ready(true)).await.unwrap();
+        loop {
+            match alice.recv().await.unwrap().unwrap() {
+                ServerMessage::Lobby { host, seats, .. } if seats[1].ready => {
+                    assert_eq!(host, Some(0));
+                    break;
+                }
+                ServerMessage::Lobby { .. } => {}
+                msg => panic!("expect lobby, got {msg:?}"),
+            }
+        }
+        alice.send(ClientMessage::Kick { seat: 1 }).await.unwrap();
+        loop {
+            match bob.next().await {
+                ServerMessage::Kicked { to } => {
+                    assert_eq!(to, Some(1));
+                    break;
+                }
+                ServerMessage::Robot { id: 2, .. } => {}
+                msg => panic!("expect kicked, got {msg:?}"),
+            }
+        }
+        assert!(bob.recv().await.unwrap().is_none());
+        // a stand-in keeps bob's seat, which leaves the table open
+        assert!(matches!(
+            alice.next().await,
+            ServerMessage::Robot { id: 1, .. }
+        ));
+        alice.send(ClientMessage::Start(true)).await.unwrap();
+        assert!(matches!(alice.next().await, ServerMessage::Refused { .. }));
+    }
+
+    #[tokio::test]
+    async fn chat_reaches_the_table() {
+        let url = serve();
+        let mut alice = Client::join(&url, "alice").await.unwrap();
+        alice.expect_joined(0, "alice").await;
+        alice
+            .send(ClientMessage::Chat {
+                text: "hi".to_string(),
+            })
+            .await
+            .unwrap();
+        let said = |msg: ServerMessage| match msg {
+            ServerMessage::Chat { line, .. } => line.said,
+            msg => panic!("expect chat, got {msg:?}"),
+        };
+        assert_eq!(said(alice.next().await), Said::Text("hi".to_string()));
+
+        // newcomers get what was said before them
+        let mut bob = Client::join(&url, "bob").await.unwrap();
+        let lines = loop {
+            match bob.recv().await.unwrap().unwrap() {
+                ServerMessage::ChatHistory { to, lines } => {
+                    assert_eq!(to, Some(1));
+                    break lines;
+                }
+                ServerMessage::Joined { .. } | ServerMessage::Lobby { .. } => {}
+                msg => panic!("expect chat history, got {msg:?}"),
+            }
+        };
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].seat, Some(0));
+        alice.expect_joined(1, "bob").await;
+
+        // spectators hear the table but the table doesn't hear them
+        let mut carol = Client::connect(&url).await.unwrap();
+        carol
+            .send(ClientMessage::Hello {
+                name: "carol".to_string(),
+                user: None,
+                reservation: None,
+                spectate: true,
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            carol.recv().await.unwrap().unwrap(),
+            ServerMessage::ChatHistory { lines, .. } if lines.is_empty()
+        ));
+        carol.send(ClientMessage::Emote(Emote::Wow)).await.unwrap();
+        assert_eq!(said(carol.next().await), Said::Emote(Emote::Wow));
+        // the table doesn't take orders from spectators either
+        bob.send(ClientMessage::Emote(Emote::WellPlayed))
+            .await
+            .unwrap();
+        assert_eq!(said(alice.next().await), Said::Emote(Emote::WellPlayed));
+        loop {
+            match carol.next().await {
+                ServerMessage::Chat { line, .. } => {
+                    assert_eq!(line.name, "bob");
+                    break;
+                }
+                ServerMessage::Joined { .. } => {}
+                msg => panic!("expect chat, got {msg:?}"),
+            }
+        }
+        for _ in 0..5 {
+            alice
+                .send(ClientMessage::Chat {
+                    text: "spam".to_string(),
+                })
+                .await
+                .unwrap();
+        }
+        loop {
+            match alice.next().await {
+                ServerMessage::Refused { reason, .. } => {
+                    assert_eq!(reason, "slow down");
+                    break;
+                }
+                ServerMessage::Chat { .. } | ServerMessage::Joined { .. } => {}
+                msg => panic!("expect refused, got {msg:?}"),
+            }
+        }
     }
 
     #[tokio::test]
     async fn basic_test1() {
         let mut builder = env_logger::Builder::from_default_env();
         builder.target(env_logger::Target::Stdout);
-        builder.init();
+        builder.try_init().ok();
 
         let mut client = connect().await;
-        client.send(ClientMessage::Test(true)).await;
-        client.send(ClientMessage::Ready(true)).await;
-        for _ in 0..2 {
-            client.send(ClientMessage::AddRobot(true)).await;
+        client.send(ClientMessage::Test(true)).await.unwrap();
+        client.send(ClientMessage::Ready(true)).await.unwrap();
+        for i in 1..3 {
+            client
+                .send(ClientMessage::AddRobot {
+                    strategy: None,
+                    difficulty: Difficulty::Hard,
+                    name: None,
+                })
+                .await
+                .unwrap();
+            client.expect_robot(i).await;
         }
-        client.send(ClientMessage::Start(true)).await;
-        let initial_hand: Vec<Card> = (0..19).into_iter().map(|n| Card(n)).collect();
+        client.send(ClientMessage::Start(true)).await.unwrap();
+        let initial_hand: Vec<Card> = (0..19).map(Card).collect();
         client.expect_initial(0, &initial_hand).await;
 
         client.expect_draw(Card(57)).await;
 
-        client.send(ClientMessage::Discard { card: Card(57) }).await;
+        client
+            .send(ClientMessage::Discard { card: Card(57) })
+            .await
+            .unwrap();
+        client.expect_discard(Card(57)).await;
         client.expect_turn(1, Mode::Normal).await;
         client.expect_discard(Card(19)).await;
         client.expect_turn(0, Mode::Pao(Card(19))).await;
 
-        client.send(ClientMessage::Pao { confirm: false }).await;
+        client
+            .send(ClientMessage::Pao { confirm: false })
+            .await
+            .unwrap();
         client.expect_turn(2, Mode::Normal).await;
         client.expect_discard(Card(38)).await;
         client.expect_turn(1, Mode::Ding(Card(38))).await;
@@ -937,27 +2321,516 @@ mod tests {
     #[tokio::test]
     async fn basic_test2() {
         let mut client = connect().await;
-        client.send(ClientMessage::Test(true)).await;
-        client.send(ClientMessage::Ready(true)).await;
-        for _ in 0..2 {
-            client.send(ClientMessage::AddRobot(true)).await;
+        client.send(ClientMessage::Test(true)).await.unwrap();
+        client.send(ClientMessage::Ready(true)).await.unwrap();
+        for i in 1..3 {
+            client
+                .send(ClientMessage::AddRobot {
+                    strategy: None,
+                    difficulty: Difficulty::Hard,
+                    name: None,
+                })
+                .await
+                .unwrap();
+            client.expect_robot(i).await;
         }
-        client.send(ClientMessage::Start(true)).await;
-        let initial_hand: Vec<Card> = (0..19).into_iter().map(|n| Card(n)).collect();
+        client.send(ClientMessage::Start(true)).await.unwrap();
+        let initial_hand: Vec<Card> = (0..19).map(Card).collect();
         client.expect_initial(0, &initial_hand).await;
 
         client.expect_draw(Card(57)).await;
 
-        client.send(ClientMessage::Discard { card: Card(57) }).await;
+        client
+            .send(ClientMessage::Discard { card: Card(57) })
+            .await
+            .unwrap();
+        client.expect_discard(Card(57)).await;
         client.expect_turn(1, Mode::Normal).await;
         client.expect_discard(Card(19)).await;
         client.expect_turn(0, Mode::Pao(Card(19))).await;
 
-        client.send(ClientMessage::Pao { confirm: true }).await;
+        client
+            .send(ClientMessage::Pao { confirm: true })
+            .await
+            .unwrap();
         client.expect_pao(Card(19)).await;
         client.expect_draw(Card(59)).await;
     }
 
+    #[tokio::test]
+    async fn bot_plays_a_hand() {
+        let mut client = connect().await;
+        client.send(ClientMessage::Test(true)).await.unwrap();
+        client.send(ClientMessage::Ready(true)).await.unwrap();
+        for i in 1..3 {
+            client
+                .send(ClientMessage::AddRobot {
+                    strategy: None,
+                    difficulty: Difficulty::Hard,
+                    name: None,
+                })
+                .await
+                .unwrap();
+            client.expect_robot(i).await;
+        }
+        client.send(ClientMessage::Start(true)).await.unwrap();
+        let mut bot = Bot::new(Strategy::Level1);
+        let mut answers = 0;
+        loop {
+            let msg = client.next().await;
+            for answer in bot.handle(&msg) {
+                client.send(answer).await.unwrap();
+                answers += 1;
+            }
+            if let ServerMessage::Hu { .. } | ServerMessage::End { .. } = msg {
+                break;
+            }
+        }
+        assert!(answers > 0);
+    }
+
+    #[test]
+    fn robots_follow_human_discards() {
+        let mut game = GameState::default();
+        game.add_player().unwrap();
+        game.players[0].ready = true;
+        for _ in 0..2 {
+            game.add_robot(Some(Strategy::Random), Difficulty::Hard, None)
+                .unwrap();
+        }
+        game.seed(7);
+        game.start().unwrap();
+        let card = game.players[0].hand[0];
+        game.discard_card(0, card).unwrap();
+        assert_eq!(game.players[0].out, [card]);
+        assert_eq!(game.players[1].player_left_out, [card]);
+        assert_eq!(game.players[2].player_right_out, [card]);
+    }
+
+    /// `is_hu` as it was before it used `eval::breakdown`
+    fn greedy_is_hu(hand: &[Card], mut score: u8, jing: Card, min_score: u8) -> bool {
+        use std::collections::HashMap;
+
+        use crate::eval::{ke_score, shun_score};
+
+        let mut hand_cnt = HashMap::new();
+        for c in hand {
+            hand_cnt.entry(c.0 / 4).and_modify(|e| *e += 1).or_insert(1);
+        }
+        fn minus_entry(cnt: &mut HashMap<u8, i32>, key: u8, val: i32) {
+            cnt.entry(key).and_modify(|e| *e -= val);
+            if cnt[&key] == 0 {
+                cnt.remove(&key);
+            }
+        }
+        for i in 0..8 {
+            let i = 3 * i;
+            let j = i + 1;
+            let k = i + 2;
+            while hand_cnt.contains_key(&i)
+                && hand_cnt.contains_key(&j)
+                && hand_cnt.contains_key(&k)
+            {
+                score += shun_score(i / 3, jing);
+                minus_entry(&mut hand_cnt, i, 1);
+                minus_entry(&mut hand_cnt, j, 1);
+                minus_entry(&mut hand_cnt, k, 1);
+            }
+        }
+        for i in 0..24 {
+            if hand_cnt.contains_key(&i) && hand_cnt[&i] >= 3 {
+                minus_entry(&mut hand_cnt, i, 3);
+                score += ke_score(i, jing);
+            }
+        }
+        if hand_cnt.len() != 2 {
+            return false;
+        }
+        let keys: Vec<&u8> = hand_cnt.keys().collect();
+        score >= min_score && keys[0] / 3 == keys[1] / 3
+    }
+
+    /// Six random melds and two cards of one category, with a card swapped
+    /// now and then so that some hands miss.
+    fn random_hand(rng: &mut StdRng) -> Vec<Card> {
+        loop {
+            let mut cnt = [0u8; 24];
+            for _ in 0..6 {
+                let kind = rng.gen_range(0..24);
+                if rng.gen() {
+                    cnt[kind] += 3;
+                } else {
+                    let first = kind / 3 * 3;
+                    for n in &mut cnt[first..first + 3] {
+                        *n += 1;
+                    }
+                }
+            }
+            let category = rng.gen_range(0..8) * 3;
+            cnt[category + rng.gen_range(0..3)] += 1;
+            cnt[category + rng.gen_range(0..3)] += 1;
+            if rng.gen_bool(0.3) {
+                let from = rng.gen_range(0..24);
+                if cnt[from] > 0 {
+                    cnt[from] -= 1;
+                    cnt[rng.gen_range(0..24)] += 1;
+                }
+            }
+            if cnt.iter().any(|&n| n > 4) {
+                continue;
+            }
+            return (0..24u8)
+                .flat_map(|kind| (0..cnt[kind as usize]).map(move |i| Card(kind * 4 + i)))
+                .collect();
+        }
+    }
+
+    #[test]
+    fn is_hu_matches_the_greedy_split() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut wins = 0;
+        for _ in 0..20000 {
+            let hand = random_hand(&mut rng);
+            let jing = Card(rng.gen_range(0..96));
+            let score = [0, 2, 6, 8][rng.gen_range(0..4)];
+            let min_score = [8, MIN_HU_SCORE, 20][rng.gen_range(0..3)];
+            let expected = greedy_is_hu(&hand, score, jing, min_score);
+            assert_eq!(
+                GameState::is_hu(&hand, score, jing, min_score),
+                expected,
+                "{hand:?}, score {score}, jing {jing:?}, min {min_score}"
+            );
+            wins += expected as usize;
+        }
+        // both outcomes are covered
+        assert!(wins > 1000 && wins < 19000, "{wins} wins");
+    }
+
+    #[test]
+    fn robot_step_waits_for_the_external_bot() {
+        let mut game = GameState::default();
+        for _ in 0..3 {
+            game.add_robot(Some(Strategy::Random), Difficulty::Normal, None)
+                .unwrap();
+        }
+        game.seed(5);
+        game.start().unwrap();
+        let seat = game.turn as usize;
+        let bot = ExternalBot::spawn(&BotConfig {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), "sleep 60".to_string()],
+            timeout_ms: 10,
+            fallback: Strategy::Random,
+        })
+        .unwrap();
+        game.players[seat].strategy = Strategy::External("silent".to_string());
+        game.players[seat].external = Some(bot);
+        let wall = game.remaining_cards.len();
+
+        assert!(matches!(
+            game.robot_step(None),
+            RobotStep::Ask(Question::Discard)
+        ));
+        let (bot, _) = game.players[seat].lend_bot().unwrap();
+        let card = game.players[seat].hand[0];
+        game.players[seat].answered(bot, Answer::Discard(card));
+        // the robot resumes with its discard and does not draw again
+        assert!(matches!(game.robot_step(None), RobotStep::Played(Some(c)) if c == card));
+        assert_eq!(game.remaining_cards.len(), wall - 1);
+    }
+
+    #[test]
+    fn rooms_limit_robots() {
+        let mut state = GameState::default();
+        let external = Some(Strategy::External("bot".to_string()));
+        assert!(Game::add_robot(&mut state, external, Difficulty::Hard, None).is_err());
+        for _ in 0..Game::MAX_ROBOTS {
+            Game::add_robot(&mut state, None, Difficulty::Hard, None).unwrap();
+        }
+        assert!(Game::add_robot(&mut state, None, Difficulty::Hard, None).is_err());
+        state.add_player().unwrap();
+        assert!(state.add_robot(None, Difficulty::Hard, None).is_err());
+    }
+
+    /// A dealt hand where robot 1 just discarded a 4 and the human at seat
+    /// 0 is offered to Ding it.
+    fn offered_a_ding() -> GameState {
+        let mut state = GameState {
+            test: true,
+            ..Default::default()
+        };
+        state.add_player().unwrap();
+        for _ in 0..2 {
+            state.add_robot(None, Difficulty::Normal, None).unwrap();
+        }
+        // the robots only play in a hand that was dealt
+        state.players[0].ready = true;
+        state.start().unwrap();
+        let cards = |cards: &[u8]| cards.iter().map(|&c| Card(c)).collect::<Vec<_>>();
+        // the human holds pairs of kinds 0 and 1, robot 1 just discarded
+        // a 1 and robot 2 is about to discard a 0
+        state.players[0].hand = cards(&[
+            1, 2, 5, 6, 8, 12, 16, 20, 24, 28, 32, 36, 40, 44, 48, 52, 56, 64, 68,
+        ]);
+        state.players[1].hand = cards(&[
+            9, 13, 17, 21, 25, 29, 33, 37, 41, 45, 49, 53, 57, 65, 69, 72, 76, 80, 84,
+        ]);
+        state.players[1].out = cards(&[4]);
+        state.players[2].hand = cards(&[
+            0, 10, 14, 18, 22, 26, 30, 34, 38, 42, 46, 50, 54, 58, 66, 70, 73, 77, 81,
+        ]);
+        state.remaining_cards = cards(&[86, 85]);
+        state.jing = Card(61);
+        state.turn = 1;
+        state.next_turn(&Card(4));
+        assert_eq!((state.turn, state.mode), (0, Mode::Ding(Card(4))));
+        state
+    }
+
+    #[tokio::test]
+    async fn decline_then_claim_the_next_discard() {
+        let state = offered_a_ding();
+        let (mut table, _) = Table::new(state, Default::default(), Duration::ZERO);
+        table
+            .play(0, ClientMessage::Ding { confirm: false })
+            .unwrap();
+        table.settle();
+        while table.robot_due.is_some() {
+            table.robot_move().await;
+            table.settle();
+        }
+
+        let state = &table.state;
+        assert_eq!((state.turn, state.mode), (0, Mode::Ding(Card(0))));
+        // the human answers the new claim before drawing
+        assert_eq!(state.players[0].hand.len(), 19);
+    }
+
+    #[test]
+    fn discards_wait_for_the_claim() {
+        let outbox = Arc::new(Outbox::default());
+        let mut inbox = outbox.open(0);
+        let (mut table, _) = Table::new(offered_a_ding(), outbox, Duration::ZERO);
+        // neither a card they hold nor one they don't is discarded
+        for card in [Card(1), Card(3)] {
+            table.play(0, ClientMessage::Discard { card }).unwrap();
+            assert!(matches!(
+                inbox.try_recv(),
+                Result::Ok(ServerMessage::Refused { to: Some(0), .. })
+            ));
+        }
+        assert_eq!(table.state.players[0].hand.len(), 19);
+        assert_eq!(table.state.mode, Mode::Ding(Card(4)));
+
+        // once they took the claim they discard as usual
+        table
+            .play(0, ClientMessage::Ding { confirm: true })
+            .unwrap();
+        table
+            .play(0, ClientMessage::Discard { card: Card(1) })
+            .unwrap();
+        assert_eq!(table.state.players[0].out, [Card(1)]);
+    }
+
+    #[tokio::test]
+    async fn spectators_follow_the_play() {
+        let outbox = Arc::new(Outbox::default());
+        let mut public = outbox.subscribe();
+        let (mut table, _) = Table::new(offered_a_ding(), outbox, Duration::ZERO);
+        table
+            .play(0, ClientMessage::Ding { confirm: true })
+            .unwrap();
+        table
+            .play(0, ClientMessage::Discard { card: Card(1) })
+            .unwrap();
+        assert!(matches!(
+            public.try_recv(),
+            Result::Ok(ServerMessage::Ding {
+                to: None,
+                seat: 0,
+                card: Card(4),
+                ..
+            })
+        ));
+        assert!(matches!(
+            public.try_recv(),
+            Result::Ok(ServerMessage::Discard {
+                to: None,
+                seat: 0,
+                card: Card(1),
+                ..
+            })
+        ));
+        // the robot after them plays and everyone sees it once
+        table.robot_move().await;
+        let discards = std::iter::from_fn(|| public.try_recv().ok())
+            .filter(|msg| matches!(msg, ServerMessage::Discard { seat: 1, .. }))
+            .count();
+        assert_eq!(discards, 1);
+    }
+
+    #[tokio::test]
+    async fn robots_take_their_time() {
+        let robot = || RobotSettings {
+            strategy: None,
+            difficulty: Difficulty::Hard,
+            name: None,
+        };
+        let settings = Settings {
+            robots: vec![robot(), robot()],
+            ..Default::default()
+        };
+        let thinking = Duration::from_millis(200);
+        let replays = Arc::new(MemoryStore::new(1));
+        let stats = Arc::new(Stats::in_memory());
+        let game = Game::new("test", &settings, replays, stats, thinking).unwrap();
+        let (id, mut inbox) = game.join("alice", None, 0).await.unwrap();
+        let messages = [
+            ClientMessage::Test(true),
+            ClientMessage::Ready(true),
+            ClientMessage::Start(true),
+            // alice deals and draws 57 in test mode
+            ClientMessage::Discard { card: Card(57) },
+        ];
+        let discarded = Instant::now();
+        for message in messages {
+            game.ask(|reply| Command::Play {
+                seat: id,
+                message,
+                reply,
+            })
+            .await
+            .unwrap();
+        }
+        let chat = ClientMessage::Chat {
+            text: "hi".to_string(),
+        };
+        game.ask(|reply| Command::Play {
+            seat: id,
+            message: chat,
+            reply,
+        })
+        .await
+        .unwrap();
+
+        // the table goes on while the robot thinks
+        let mut chatted = false;
+        loop {
+            match inbox.recv().await.unwrap() {
+                ServerMessage::Chat { .. } => chatted = true,
+                ServerMessage::Discard { seat: 1, .. } => break,
+                _ => {}
+            }
+        }
+        assert!(chatted);
+        assert!(discarded.elapsed() >= thinking);
+    }
+
+    #[test]
+    fn robots_see_the_wall() {
+        let mut game = GameState::default();
+        for _ in 0..3 {
+            game.add_robot(Some(Strategy::Random), Difficulty::Normal, None)
+                .unwrap();
+        }
+        game.seed(11);
+        game.start().unwrap();
+        game.draw_card();
+        for p in &game.players {
+            assert_eq!(p.observe().wall as usize, game.remaining_cards.len());
+        }
+    }
+
+    #[test]
+    fn humans_leave_their_seats_to_stand_ins() {
+        let mut game = GameState::default();
+        let alice = game.add_player().unwrap();
+        let bob = game.add_player().unwrap();
+        game.add_robot(None, Difficulty::Normal, None).unwrap();
+        game.leave(alice);
+        game.leave(bob);
+        assert_eq!(game.players.len(), 3);
+        assert!(game.players[..2].iter().all(|p| p.is_robot && p.stand_in));
+        assert!(game
+            .players
+            .iter()
+            .enumerate()
+            .all(|(i, p)| p.id as usize == i));
+        // a newcomer takes the first seat left
+        assert_eq!(game.add_player().unwrap(), alice);
+        assert!(!game.players[alice as usize].is_robot);
+    }
+
+    #[test]
+    fn stand_in_discards_for_a_human_who_drew() {
+        let mut game = GameState::default();
+        game.add_player().unwrap();
+        game.players[0].ready = true;
+        for _ in 0..2 {
+            game.add_robot(Some(Strategy::Random), Difficulty::Normal, None)
+                .unwrap();
+        }
+        game.seed(3);
+        game.start().unwrap();
+        while !game.is_over() && game.is_robot_turn() {
+            if let Some(card) = game.robot_turn(None) {
+                game.next_turn(&card);
+            }
+        }
+        assert!(!game.is_over() && game.mode == Mode::Normal);
+        game.draw_card();
+        let wall = game.remaining_cards.len();
+        game.leave(0);
+        assert!(game.robot_turn(None).is_some());
+        assert_eq!(game.remaining_cards.len(), wall);
+        game.players[0].check_state(19);
+    }
+
+    #[test]
+    fn hands_count_for_accounts() {
+        let stats = Arc::new(Stats::in_memory());
+        let mut won = 0;
+        for seed in 0..20 {
+            let mut game = GameState::default();
+            for _ in 0..3 {
+                game.add_robot(Some(Strategy::Level1), Difficulty::Hard, None)
+                    .unwrap();
+            }
+            game.players[0].account = Some("alice".to_string());
+            game.stats = Some(stats.clone());
+            game.seed(seed);
+            game.start().unwrap();
+            while !game.is_over() {
+                if let Some(card) = game.robot_turn(None) {
+                    game.next_turn(&card);
+                }
+            }
+            if game.replay().unwrap().winner() == Some(0) {
+                won += 1;
+            }
+            let events = &game.replay().unwrap().events;
+            let paos = events
+                .iter()
+                .filter(|e| matches!(e, Event::Pao { seat: 0, .. }))
+                .count();
+            let alice = stats.get("alice").unwrap();
+            assert_eq!(alice.hands, seed as u32 + 1);
+            assert_eq!(alice.wins, won);
+            assert!(alice.self_draws <= alice.wins);
+            assert!(alice.paos as usize >= paos);
+        }
+        assert!(won > 0);
+    }
+
+    #[test]
+    fn robot_names_are_capped() {
+        let mut game = GameState::default();
+        game.add_robot(None, Difficulty::Hard, Some("x".repeat(100)))
+            .unwrap();
+        assert_eq!(game.players[0].name.len(), GameState::MAX_NAME);
+        assert_eq!(game.players[0].strategy, Strategy::Lookahead);
+    }
+
     #[test]
     fn test_hu() {
         // let mut builder = env_logger::Builder::from_default_env();
@@ -967,19 +2840,19 @@ mod tests {
         let hand = [
             0, 4, 8, 1, 5, 9, 12, 13, 14, 15, 16, 20, 24, 28, 32, 25, 29, 33, 34, 26,
         ];
-        let hand = hand.into_iter().map(|n| Card(n)).collect();
-        assert_eq!(GameState::is_hu(&hand, 0, Card(90)), true);
+        let hand: Vec<Card> = hand.into_iter().map(Card).collect();
+        assert!(GameState::is_hu(&hand, 0, Card(90), MIN_HU_SCORE));
         // 0 1 2 / 0 1 2 / 3 3 3 / 3 4 5 / 6 7 8 / 6 7 8 / 8 9
         let hand = [
             0, 4, 8, 1, 5, 9, 12, 13, 14, 15, 16, 20, 24, 28, 32, 25, 29, 33, 34, 36,
         ];
-        let hand = hand.into_iter().map(|n| Card(n)).collect();
-        assert_eq!(GameState::is_hu(&hand, 0, Card(90)), false);
+        let hand: Vec<Card> = hand.into_iter().map(Card).collect();
+        assert!(!GameState::is_hu(&hand, 0, Card(90), MIN_HU_SCORE));
         // 1 2 / 0 1 2 / 3 3 3 / 3 4 5 / 6 7 8 / 6 7 8 / 7 8 9
         let hand = [
             4, 8, 1, 5, 9, 12, 13, 14, 15, 16, 20, 24, 28, 32, 25, 29, 33, 30, 34, 36,
         ];
-        let hand = hand.into_iter().map(|n| Card(n)).collect();
-        assert_eq!(GameState::is_hu(&hand, 0, Card(90)), false);
+        let hand: Vec<Card> = hand.into_iter().map(Card).collect();
+        assert!(!GameState::is_hu(&hand, 0, Card(90), MIN_HU_SCORE));
     }
 }
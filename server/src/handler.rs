@@ -1,26 +1,777 @@
-use log::debug;
-use warp::{filters::ws::Ws, reject::Rejection, reply::Reply};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use warp::{
+    filters::ws::Ws,
+    http::{header, StatusCode},
+    reject::Rejection,
+    reply::{self, Reply, Response},
+    Filter,
+};
 
-use crate::{room::Room, GlobalState};
+use crate::{
+    accounts::Credentials,
+    agent::Strategy,
+    game::Game,
+    history::Query,
+    matchmaking::MatchQuery,
+    room::{Room, Settings},
+    stats::Standing,
+    GlobalState,
+};
+
+/// Every route of the server, sharing `state`.
+pub fn routes(
+    state: GlobalState,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let state = warp::any().map(move || state.clone());
+    let ws = warp::path!("api" / "ws" / String)
+        .and(warp::ws())
+        .and(warp::query::<SocketQuery>())
+        .and(state.clone())
+        .and_then(socket_handler);
+    let play_now = warp::path!("api" / "match")
+        .and(warp::ws())
+        .and(warp::query::<MatchQuery>())
+        .and(state.clone())
+        .and_then(match_handler);
+    let list = warp::path!("api" / "replays")
+        .and(warp::get())
+        .and(warp::query::<Query>())
+        .and(state.clone())
+        .and_then(list_replays);
+    let get = warp::path!("api" / "replays" / String)
+        .and(warp::get())
+        .and(state.clone())
+        .and_then(|id, state| replay_handler(id, state, false));
+    let download = warp::path!("api" / "replays" / String / "download")
+        .and(warp::get())
+        .and(state.clone())
+        .and_then(|id, state| replay_handler(id, state, true));
+    let create_room = warp::path!("api" / "rooms")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::json())
+        .and(state.clone())
+        .and_then(create_room);
+    let list_rooms = warp::path!("api" / "rooms")
+        .and(warp::get())
+        .and(warp::query::<RoomQuery>())
+        .and(state.clone())
+        .and_then(list_rooms);
+    let get_room = warp::path!("api" / "rooms" / String)
+        .and(warp::get())
+        .and(state.clone())
+        .and_then(get_room);
+    let close_room = warp::path!("api" / "rooms" / String)
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(state.clone())
+        .and_then(close_room);
+    let register = warp::path!("api" / "accounts")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(4 * 1024))
+        .and(warp::body::json())
+        .and(state.clone())
+        .and_then(register);
+    let login = warp::path!("api" / "sessions")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(4 * 1024))
+        .and(warp::body::json())
+        .and(state.clone())
+        .and_then(login);
+    let logout = warp::path!("api" / "sessions")
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(state.clone())
+        .and_then(logout);
+    let get_profile = warp::path!("api" / "profile")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(state.clone())
+        .and_then(get_profile);
+    let rename = warp::path!("api" / "profile")
+        .and(warp::put())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::content_length_limit(4 * 1024))
+        .and(warp::body::json())
+        .and(state.clone())
+        .and_then(rename);
+    let leaderboard = warp::path!("api" / "leaderboard")
+        .and(warp::get())
+        .and(warp::query::<LeaderboardQuery>())
+        .and(state.clone())
+        .and_then(leaderboard);
+    let player_stats = warp::path!("api" / "stats" / String)
+        .and(warp::get())
+        .and(state)
+        .and_then(player_stats);
+    ws.or(play_now)
+        .or(list)
+        .or(get)
+        .or(download)
+        .or(create_room)
+        .or(list_rooms)
+        .or(get_room)
+        .or(close_room)
+        .or(register)
+        .or(login)
+        .or(logout)
+        .or(get_profile)
+        .or(rename)
+        .or(leaderboard)
+        .or(player_stats)
+}
+
+/// How a websocket connects, browsers can't set headers on it.
+#[derive(Deserialize)]
+pub struct SocketQuery {
+    /// the session of a logged in player, guests have none
+    token: Option<String>,
+    /// the invite code of a private room
+    invite: Option<String>,
+    /// the password of a private room, instead of the invite code
+    password: Option<String>,
+}
 
 pub async fn socket_handler(
     id: String,
     ws: Ws,
+    query: SocketQuery,
     state: GlobalState,
-) -> Result<impl Reply, Rejection> {
+) -> Result<Response, Rejection> {
     use dashmap::mapref::entry::Entry;
     debug!("id: {id}");
 
+    let account = match query.token {
+        Some(token) => match state.accounts.authenticate(&token) {
+            Some(profile) => Some(profile),
+            None => return Ok(StatusCode::UNAUTHORIZED.into_response()),
+        },
+        None => None,
+    };
+
     let entry = match state.rooms.entry(id.clone()) {
         Entry::Occupied(e) => e.into_ref(),
         Entry::Vacant(e) => {
-            let room = Room::new();
+            let room = Room::new(&id, &state, &Settings::default())
+                .expect("the default settings seat no robots");
             e.insert(room)
         }
     };
 
-    let room = entry.value();
-    let game = room.game.clone();
+    let room = entry.value().clone();
+    drop(entry);
+    if !room.admits(query.invite.as_deref(), query.password.as_deref()) {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+
+    Ok(ws
+        .on_upgrade(|socket| async move { room.on_connection(socket, account).await })
+        .into_response())
+}
+
+/// Queue a player until a table is formed for them.
+pub async fn match_handler(
+    ws: Ws,
+    query: MatchQuery,
+    state: GlobalState,
+) -> Result<Response, Rejection> {
+    let pool = match query.pool() {
+        Ok(pool) => pool,
+        Err(e) => {
+            return Ok(reply::with_status(e.to_string(), StatusCode::BAD_REQUEST).into_response())
+        }
+    };
+    let robots = query.robots.unwrap_or(Strategy::Level1);
+    if let Err(e) = Game::check_robot(Some(&robots)) {
+        return Ok(reply::with_status(e.to_string(), StatusCode::BAD_REQUEST).into_response());
+    }
+    Ok(ws
+        .on_upgrade(move |socket| async move {
+            let matchmaker = state.matchmaker.clone();
+            matchmaker.on_connection(&state, pool, robots, socket).await
+        })
+        .into_response())
+}
+
+/// Create a room under a fresh id.
+pub async fn create_room(settings: Settings, state: GlobalState) -> Result<Response, Rejection> {
+    Ok(match state.create_room(&settings) {
+        Ok(info) => reply::with_status(reply::json(&info), StatusCode::CREATED).into_response(),
+        Err(e) => reply::with_status(e.to_string(), StatusCode::BAD_REQUEST).into_response(),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct RoomQuery {
+    /// list the full rooms and the ones playing too
+    #[serde(default)]
+    all: bool,
+}
+
+/// The rooms someone can join.
+pub async fn list_rooms(query: RoomQuery, state: GlobalState) -> Result<Response, Rejection> {
+    state.sweep();
+    let mut rooms: Vec<_> = state
+        .rooms
+        .iter()
+        .filter(|r| !r.is_private())
+        .map(|r| r.info())
+        .filter(|info| query.all || info.is_open())
+        .collect();
+    rooms.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(reply::json(&rooms).into_response())
+}
+
+pub async fn get_room(id: String, state: GlobalState) -> Result<Response, Rejection> {
+    match state.rooms.get(&id) {
+        // a private room is not there for whoever guesses its id
+        Some(room) if !room.is_private() => Ok(reply::json(&room.info()).into_response()),
+        _ => Ok(not_found()),
+    }
+}
+
+/// Remove a room and hang up on its players, for the creator of the room
+/// or an admin with a `Bearer` token.
+pub async fn close_room(
+    id: String,
+    authorization: Option<String>,
+    state: GlobalState,
+) -> Result<Response, Rejection> {
+    let Some(token) = bearer(&authorization) else {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    };
+    let allowed = match state.rooms.get(&id) {
+        Some(room) => room.is_creator(token) || state.is_admin(token),
+        None => return Ok(not_found()),
+    };
+    if !allowed {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    match state.rooms.remove(&id) {
+        Some((_, room)) => {
+            room.game.close();
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        None => Ok(not_found()),
+    }
+}
+
+/// the token of an `Authorization: Bearer` header
+fn bearer(authorization: &Option<String>) -> Option<&str> {
+    authorization
+        .as_deref()
+        .and_then(|a| a.strip_prefix("Bearer "))
+}
+
+/// Create an account, hashing takes a while so it runs off the runtime.
+pub async fn register(credentials: Credentials, state: GlobalState) -> Result<Response, Rejection> {
+    if state.accounts.profile(credentials.user.trim()).is_some() {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+    let accounts = state.accounts.clone();
+    Ok(
+        match tokio::task::spawn_blocking(move || accounts.register(&credentials)).await {
+            Ok(Ok(profile)) => {
+                reply::with_status(reply::json(&profile), StatusCode::CREATED).into_response()
+            }
+            Ok(Err(e)) => {
+                reply::with_status(e.to_string(), StatusCode::BAD_REQUEST).into_response()
+            }
+            Err(e) => internal_error(e.into()),
+        },
+    )
+}
+
+/// Open a session for the right password.
+pub async fn login(credentials: Credentials, state: GlobalState) -> Result<Response, Rejection> {
+    let accounts = state.accounts.clone();
+    Ok(
+        match tokio::task::spawn_blocking(move || accounts.login(&credentials)).await {
+            Ok(Ok(Some(session))) => {
+                reply::with_status(reply::json(&session), StatusCode::CREATED).into_response()
+            }
+            Ok(Ok(None)) => StatusCode::UNAUTHORIZED.into_response(),
+            Ok(Err(e)) => internal_error(e),
+            Err(e) => internal_error(e.into()),
+        },
+    )
+}
+
+pub async fn logout(
+    authorization: Option<String>,
+    state: GlobalState,
+) -> Result<Response, Rejection> {
+    let Some(token) = bearer(&authorization) else {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    };
+    Ok(match state.accounts.logout(token) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => internal_error(e),
+    })
+}
+
+pub async fn get_profile(
+    authorization: Option<String>,
+    state: GlobalState,
+) -> Result<Response, Rejection> {
+    match bearer(&authorization).and_then(|t| state.accounts.authenticate(t)) {
+        Some(profile) => Ok(reply::json(&profile).into_response()),
+        None => Ok(StatusCode::UNAUTHORIZED.into_response()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Rename {
+    name: String,
+}
+
+/// Change the name a logged in player is shown with.
+pub async fn rename(
+    authorization: Option<String>,
+    rename: Rename,
+    state: GlobalState,
+) -> Result<Response, Rejection> {
+    let Some(profile) = bearer(&authorization).and_then(|t| state.accounts.authenticate(t)) else {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    };
+    Ok(match state.accounts.rename(&profile.user, &rename.name) {
+        Ok(profile) => reply::json(&profile).into_response(),
+        Err(e) => reply::with_status(e.to_string(), StatusCode::BAD_REQUEST).into_response(),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct LeaderboardQuery {
+    limit: Option<usize>,
+}
+
+/// A line of the leaderboard, with the name the player goes by.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Entry {
+    pub name: String,
+    #[serde(flatten)]
+    pub standing: Standing,
+}
+
+/// The best rated players.
+pub async fn leaderboard(
+    query: LeaderboardQuery,
+    state: GlobalState,
+) -> Result<Response, Rejection> {
+    let entries: Vec<_> = state
+        .stats
+        .leaderboard(query.limit.unwrap_or(50).min(500))
+        .into_iter()
+        .map(|standing| Entry {
+            name: state
+                .accounts
+                .profile(&standing.user)
+                .map_or_else(|| standing.user.clone(), |p| p.name),
+            standing,
+        })
+        .collect();
+    Ok(reply::json(&entries).into_response())
+}
+
+pub async fn player_stats(user: String, state: GlobalState) -> Result<Response, Rejection> {
+    if state.accounts.profile(&user).is_none() {
+        return Ok(not_found());
+    }
+    Ok(reply::json(&state.stats.get(&user).unwrap_or_default()).into_response())
+}
+
+fn not_found() -> Response {
+    StatusCode::NOT_FOUND.into_response()
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    warn!("{e:#}");
+    reply::with_status(e.to_string(), StatusCode::INTERNAL_SERVER_ERROR).into_response()
+}
+
+/// The finished hands, filtered by room and player.
+pub async fn list_replays(query: Query, state: GlobalState) -> Result<Response, Rejection> {
+    // a file store reads its index from disk
+    let listed = tokio::task::spawn_blocking(move || state.replays.list(&query)).await;
+    Ok(match listed.map_err(anyhow::Error::from).and_then(|r| r) {
+        Ok(summaries) => reply::json(&summaries).into_response(),
+        Err(e) => internal_error(e),
+    })
+}
+
+/// The replay of a hand, as an attachment when `download` is set.
+pub async fn replay_handler(
+    id: String,
+    state: GlobalState,
+    download: bool,
+) -> Result<Response, Rejection> {
+    let loaded = tokio::task::spawn_blocking(move || state.replays.load(&id)).await;
+    let replay = match loaded.map_err(anyhow::Error::from).and_then(|r| r) {
+        Ok(Some(replay)) => replay,
+        Ok(None) => return Ok(not_found()),
+        Err(e) => return Ok(internal_error(e)),
+    };
+    let response = reply::json(&replay);
+    if !download {
+        return Ok(response.into_response());
+    }
+    let disposition = format!("attachment; filename=\"{}\"", replay.file_name());
+    Ok(reply::with_header(response, header::CONTENT_DISPOSITION, disposition).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    use crate::{
+        accounts::{Profile, Session},
+        agent::{Difficulty, Strategy},
+        client::Client,
+        game::{GameState, ServerMessage},
+        history::{MemoryStore, Summary},
+        matchmaking::{MatchMessage, Matchmaker},
+        replay::Replay,
+        room::{Created, RoomInfo},
+        rules::Rules,
+        stats::{PlayerStats, SeatResult},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replay_routes() {
+        let mut game = GameState::default();
+        game.room = "room".to_string();
+        for _ in 0..3 {
+            game.add_robot(Some(Strategy::Random), Difficulty::Hard, None)
+                .unwrap();
+        }
+        game.replays = Some(Arc::new(MemoryStore::new(10)));
+        let state = GlobalState::with_replays(game.replays.clone().unwrap());
+        // off the runtime the replay is saved before the hand is over
+        let played = std::thread::spawn(move || {
+            game.start().unwrap();
+            while !game.is_over() {
+                if let Some(card) = game.robot_turn(None) {
+                    game.next_turn(&card);
+                }
+            }
+            game.replay().unwrap().clone()
+        })
+        .join()
+        .unwrap();
+        let id = played.id();
+        let routes = routes(state);
+
+        let res = warp::test::request()
+            .path("/api/replays?room=room")
+            .reply(&routes)
+            .await;
+        let summaries: Vec<Summary> = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].id, id);
+        let res = warp::test::request()
+            .path("/api/replays?player=nobody")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.body().as_ref(), b"[]");
+
+        let res = warp::test::request()
+            .path(&format!("/api/replays/{id}"))
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let replay = Replay::parse(std::str::from_utf8(res.body()).unwrap()).unwrap();
+        assert_eq!(replay, played);
+
+        let res = warp::test::request()
+            .path(&format!("/api/replays/{id}/download"))
+            .reply(&routes)
+            .await;
+        assert!(res.headers()[header::CONTENT_DISPOSITION]
+            .to_str()
+            .unwrap()
+            .contains(&replay.file_name()));
+
+        let res = warp::test::request()
+            .path("/api/replays/missing")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_lobby_routes() {
+        let routes = routes(GlobalState::with_replays(Arc::new(MemoryStore::new(10))));
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/rooms")
+            .json(&serde_json::json!({
+                "rules": {"min_hu_score": 8, "claims": true},
+                "robots": [{"strategy": "Random", "name": "bob"}],
+            }))
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let Created {
+            info: room,
+            token,
+            invite,
+        } = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(invite, None);
+        assert_eq!(room.rules.min_hu_score, 8);
+        assert_eq!(room.seats.len(), 1);
+        assert_eq!(room.seats[0].name, "bob");
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/rooms")
+            .json(&serde_json::json!({"robots": [{}, {}, {}]}))
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = warp::test::request()
+            .path("/api/rooms")
+            .reply(&routes)
+            .await;
+        let rooms: Vec<RoomInfo> = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].id, room.id);
+
+        let path = format!("/api/rooms/{}", room.id);
+        let res = warp::test::request().path(&path).reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let close = |token: Option<&str>| {
+            let request = warp::test::request().method("DELETE").path(&path);
+            match token {
+                Some(token) => request.header("authorization", format!("Bearer {token}")),
+                None => request,
+            }
+        };
+        let res = close(None).reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = close(Some("guess")).reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = close(Some(&token)).reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res = warp::test::request().path(&path).reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_private_rooms() {
+        let state = GlobalState::with_replays(Arc::new(MemoryStore::new(10)));
+        let routes = routes(state.clone());
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/rooms")
+            .json(&serde_json::json!({"password": "weekly"}))
+            .reply(&routes)
+            .await;
+        let created: Created = serde_json::from_slice(res.body()).unwrap();
+        let id = created.info.id;
+        let invite = created.invite.unwrap();
+        let res = warp::test::request()
+            .path("/api/rooms?all=true")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.body().as_ref(), b"[]");
+        let res = warp::test::request()
+            .path(&format!("/api/rooms/{id}"))
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        for (query, status) in [
+            ("", StatusCode::FORBIDDEN),
+            ("?password=guess", StatusCode::FORBIDDEN),
+            ("?password=weekly", StatusCode::SWITCHING_PROTOCOLS),
+            (
+                &format!("?invite={invite}"),
+                StatusCode::SWITCHING_PROTOCOLS,
+            ),
+        ] {
+            let res = warp::test::request()
+                .path(&format!("/api/ws/{id}{query}"))
+                .header("connection", "upgrade")
+                .header("upgrade", "websocket")
+                .header("sec-websocket-version", "13")
+                .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), status, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_closes_rooms() {
+        let state =
+            GlobalState::with_replays(Arc::new(MemoryStore::new(10))).with_admin("admin-token");
+        let room = state.create_room(&Settings::default()).unwrap();
+        let routes = routes(state);
+        let res = warp::test::request()
+            .method("DELETE")
+            .path(&format!("/api/rooms/{}", room.info.id))
+            .header("authorization", "Bearer admin-token")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    }
+
+    async fn next<S>(socket: &mut S) -> MatchMessage
+    where
+        S: futures::Stream<Item = tokio_tungstenite::tungstenite::Result<WsMessage>> + Unpin,
+    {
+        let message = socket.next().await.unwrap().unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_match_route() {
+        let state = GlobalState::with_replays(Arc::new(MemoryStore::new(10)))
+            .with_matchmaker(Matchmaker::new(Duration::from_millis(50)));
+        let (addr, server) = warp::serve(routes(state.clone())).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{addr}/api/match?rules=casual"))
+                .await
+                .unwrap();
+        assert_eq!(next(&mut socket).await, MatchMessage::Queued { waiting: 1 });
+        let MatchMessage::Matched { room, reservation } = next(&mut socket).await else {
+            panic!("not matched");
+        };
+        let info = state.rooms.get(&room).unwrap().info();
+        assert_eq!(info.rules, Rules::CASUAL);
+        assert_eq!(info.seats.len(), 2);
+        assert_eq!(info.reserved, 1);
+        assert!(!info.is_open());
+        assert!(!reservation.is_empty());
+
+        let res = warp::test::request()
+            .path("/api/match?rules=unknown")
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .reply(&routes(state))
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_accounts() {
+        let state = GlobalState::with_replays(Arc::new(MemoryStore::new(10)));
+        let routes = routes(state.clone());
+        let credentials = serde_json::json!({"user": "alice", "password": "hunter22"});
+        for status in [StatusCode::CREATED, StatusCode::CONFLICT] {
+            let res = warp::test::request()
+                .method("POST")
+                .path("/api/accounts")
+                .json(&credentials)
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), status);
+        }
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/sessions")
+            .json(&serde_json::json!({"user": "alice", "password": "wrong"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/sessions")
+            .json(&credentials)
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let session: Session = serde_json::from_slice(res.body()).unwrap();
+        let bearer = format!("Bearer {}", session.token);
+        let res = warp::test::request()
+            .method("PUT")
+            .path("/api/profile")
+            .header("authorization", &bearer)
+            .json(&serde_json::json!({"name": "Alice"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = warp::test::request()
+            .path("/api/profile")
+            .header("authorization", &bearer)
+            .reply(&routes)
+            .await;
+        let profile: Profile = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(profile.name, "Alice");
+
+        // a forged session is turned away before the upgrade
+        let res = warp::test::request()
+            .path("/api/ws/room?token=forged")
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        // a logged in player sits under their account, whatever they say
+        let (addr, server) = warp::serve(routes.clone()).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let url = format!("ws://{addr}/api/ws/room?token={}", session.token);
+        let mut client = Client::join(&url, "mallory").await.unwrap();
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Some(ServerMessage::Joined { name, .. }) if name == "Alice"
+        ));
+        assert_eq!(state.rooms.get("room").unwrap().seat_of("alice"), Some(0));
+
+        let res = warp::test::request()
+            .method("DELETE")
+            .path("/api/sessions")
+            .header("authorization", &bearer)
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res = warp::test::request()
+            .path("/api/profile")
+            .header("authorization", &bearer)
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
 
-    Ok(ws.on_upgrade(|socket| async move { game.on_connection(socket).await }))
+        let win = SeatResult {
+            account: Some("alice".to_string()),
+            won: true,
+            score: Some(20),
+            ..Default::default()
+        };
+        state
+            .stats
+            .record(&[win, SeatResult::default(), SeatResult::default()])
+            .unwrap();
+        let res = warp::test::request()
+            .path("/api/leaderboard")
+            .reply(&routes)
+            .await;
+        let entries: Vec<Entry> = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "Alice");
+        assert_eq!(entries[0].standing.average_score, Some(20.0));
+        let res = warp::test::request()
+            .path("/api/stats/alice")
+            .reply(&routes)
+            .await;
+        let stats: PlayerStats = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(stats.wins, 1);
+        let res = warp::test::request()
+            .path("/api/stats/nobody")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
 }
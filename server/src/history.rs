@@ -0,0 +1,324 @@
+use std::{
+    collections::VecDeque,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use log::warn;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::replay::{Event, Replay};
+
+/// Where finished hands are kept.
+pub trait ReplayStore: Send + Sync {
+    fn save(&self, replay: &Replay) -> Result<()>;
+    /// the hands matching `query`, the most recent first
+    fn list(&self, query: &Query) -> Result<Vec<Summary>>;
+    fn load(&self, id: &str) -> Result<Option<Replay>>;
+}
+
+/// Which hands to list.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct Query {
+    pub room: Option<String>,
+    /// the name of one of the seats
+    pub player: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    const LIMIT: usize = 50;
+
+    /// the hands of private rooms are only found by their id
+    fn matches(&self, summary: &Summary) -> bool {
+        !summary.private
+            && self.room.as_ref().is_none_or(|r| *r == summary.room)
+            && self
+                .player
+                .as_ref()
+                .is_none_or(|p| summary.players.contains(p))
+    }
+
+    fn select(&self, summaries: impl Iterator<Item = Summary>) -> Vec<Summary> {
+        let mut summaries: Vec<Summary> = summaries.filter(|s| self.matches(s)).collect();
+        summaries.sort_by(|a, b| b.started.cmp(&a.started).then(b.id.cmp(&a.id)));
+        summaries.truncate(self.limit.unwrap_or(Self::LIMIT));
+        summaries
+    }
+}
+
+/// A finished hand in a listing.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Summary {
+    pub id: String,
+    pub room: String,
+    pub started: u64,
+    pub players: Vec<String>,
+    pub winner: Option<u8>,
+    pub score: Option<u8>,
+    pub events: usize,
+    #[serde(default)]
+    pub private: bool,
+}
+
+impl From<&Replay> for Summary {
+    fn from(replay: &Replay) -> Self {
+        Self {
+            id: replay.id(),
+            room: replay.room.clone(),
+            started: replay.started,
+            players: replay.seats.iter().map(|s| s.name.clone()).collect(),
+            winner: replay.winner(),
+            score: match replay.result() {
+                Some(Event::Hu { score, .. }) => score,
+                _ => None,
+            },
+            events: replay.events.len(),
+            private: replay.private,
+        }
+    }
+}
+
+/// One JSON file per hand in a directory, with the summaries of all of
+/// them in `index.jsonl` so that listing does not read every hand.
+pub struct FileStore {
+    dir: PathBuf,
+    /// keeps the lines of concurrent saves apart
+    index: Mutex<()>,
+}
+
+impl FileStore {
+    const INDEX: &'static str = "index.jsonl";
+
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            index: Mutex::new(()),
+        }
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join(Self::INDEX)
+    }
+
+    fn append(path: &Path, summaries: &[Summary]) -> Result<()> {
+        let mut lines = String::new();
+        for summary in summaries {
+            lines += &serde_json::to_string(summary)?;
+            lines.push('\n');
+        }
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(lines.as_bytes()))
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Index the hands of a directory written before there was an index.
+    fn rebuild(&self) -> Result<Vec<Summary>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", self.dir.display()))
+            }
+        };
+        let mut summaries = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            match Replay::load(&path) {
+                Ok(replay) => summaries.push(Summary::from(&replay)),
+                Err(e) => warn!("skipping a replay: {e:#}"),
+            }
+        }
+        Self::append(&self.index_path(), &summaries)?;
+        Ok(summaries)
+    }
+}
+
+impl ReplayStore for FileStore {
+    fn save(&self, replay: &Replay) -> Result<()> {
+        let _index = self.index.lock();
+        let indexed = self.index_path().exists();
+        replay.save(&self.dir)?;
+        if indexed {
+            Self::append(&self.index_path(), &[Summary::from(replay)])?;
+        } else {
+            self.rebuild()?;
+        }
+        Ok(())
+    }
+
+    fn list(&self, query: &Query) -> Result<Vec<Summary>> {
+        let _index = self.index.lock();
+        let path = self.index_path();
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(query.select(self.rebuild()?.into_iter()))
+            }
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let summaries = content
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(summary) => Some(summary),
+                Err(e) => {
+                    warn!("skipping a line of {}: {e}", path.display());
+                    None
+                }
+            });
+        Ok(query.select(summaries))
+    }
+
+    fn load(&self, id: &str) -> Result<Option<Replay>> {
+        // ids come from urls, keep them inside the directory
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Ok(None);
+        }
+        let path = self.dir.join(format!("{id}.json"));
+        if !path.exists() {
+            return Ok(None);
+        }
+        Replay::load(&path).map(Some)
+    }
+}
+
+/// The last hands in memory, for servers without a replay directory.
+pub struct MemoryStore {
+    replays: Mutex<VecDeque<Replay>>,
+    capacity: usize,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            replays: Default::default(),
+            capacity,
+        }
+    }
+}
+
+impl ReplayStore for MemoryStore {
+    fn save(&self, replay: &Replay) -> Result<()> {
+        let mut replays = self.replays.lock();
+        if replays.len() == self.capacity {
+            replays.pop_front();
+        }
+        replays.push_back(replay.clone());
+        Ok(())
+    }
+
+    fn list(&self, query: &Query) -> Result<Vec<Summary>> {
+        Ok(query.select(self.replays.lock().iter().map(Summary::from)))
+    }
+
+    fn load(&self, id: &str) -> Result<Option<Replay>> {
+        Ok(self.replays.lock().iter().find(|r| r.id() == id).cloned())
+    }
+}
+
+/// Files in the directory named by `SHANGDAREN_REPLAYS`, or the last
+/// thousand hands in memory when it is not set.
+pub fn configured() -> Arc<dyn ReplayStore> {
+    match std::env::var_os("SHANGDAREN_REPLAYS") {
+        Some(dir) => Arc::new(FileStore::new(dir)),
+        None => Arc::new(MemoryStore::new(1000)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        agent::{Difficulty, Strategy},
+        game::GameState,
+    };
+
+    use super::*;
+
+    fn play(room: &str, seed: u64) -> Replay {
+        let mut game = GameState::default();
+        game.room = room.to_string();
+        for _ in 0..3 {
+            game.add_robot(Some(Strategy::Random), Difficulty::Hard, None)
+                .unwrap();
+        }
+        game.seed(seed);
+        game.start().unwrap();
+        while !game.is_over() {
+            if let Some(card) = game.robot_turn(None) {
+                game.next_turn(&card);
+            }
+        }
+        game.replay().unwrap().clone()
+    }
+
+    fn check(store: &dyn ReplayStore) {
+        let a = play("a", 1);
+        let b = play("b", 2);
+        store.save(&a).unwrap();
+        store.save(&b).unwrap();
+        assert_eq!(store.list(&Query::default()).unwrap().len(), 2);
+        let query = Query {
+            room: Some("b".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(store.list(&query).unwrap(), vec![Summary::from(&b)]);
+        let query = Query {
+            player: Some("robot 1".to_string()),
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(store.list(&query).unwrap().len(), 1);
+        assert_eq!(store.load(&a.id()).unwrap(), Some(a));
+        assert_eq!(store.load("missing").unwrap(), None);
+        assert_eq!(store.load("../a").unwrap(), None);
+
+        // private rooms are not listed, even by name
+        let c = Replay {
+            private: true,
+            ..play("c", 3)
+        };
+        store.save(&c).unwrap();
+        let query = Query {
+            room: Some("c".to_string()),
+            ..Default::default()
+        };
+        assert!(store.list(&query).unwrap().is_empty());
+        assert_eq!(store.load(&c.id()).unwrap(), Some(c));
+    }
+
+    #[test]
+    fn test_memory_store() {
+        check(&MemoryStore::new(10));
+        let store = MemoryStore::new(1);
+        store.save(&play("a", 1)).unwrap();
+        store.save(&play("a", 2)).unwrap();
+        assert_eq!(store.list(&Query::default()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_file_store() {
+        let dir = std::env::temp_dir().join(format!("shangdaren-history-{}", std::process::id()));
+        check(&FileStore::new(&dir));
+        // listing reads the index, and rebuilds it when it is missing
+        std::fs::write(dir.join("junk.json"), "{}").unwrap();
+        assert_eq!(
+            FileStore::new(&dir).list(&Query::default()).unwrap().len(),
+            2
+        );
+        std::fs::remove_file(dir.join(FileStore::INDEX)).unwrap();
+        let store = FileStore::new(&dir);
+        assert_eq!(store.list(&Query::default()).unwrap().len(), 2);
+        store.save(&play("c", 3)).unwrap();
+        assert_eq!(store.list(&Query::default()).unwrap().len(), 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
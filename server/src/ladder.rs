@@ -0,0 +1,238 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    agent::Strategy,
+    rules::Rules,
+    simulate::{sub_seed, Report, Simulation},
+};
+
+/// the rating every strategy starts from
+const INITIAL_RATING: f64 = 1500.0;
+/// how far a single match moves the ratings
+const K: f64 = 32.0;
+
+/// How the ladder seats strategies together.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Schedule {
+    /// every group of three strategies plays once per round
+    RoundRobin,
+    /// strategies of similar rating play together
+    Swiss,
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    /// `round-robin` or `swiss`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "round-robin" => Ok(Schedule::RoundRobin),
+            "swiss" => Ok(Schedule::Swiss),
+            _ => bail!("unknown schedule {s}"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Entry {
+    pub strategy: String,
+    pub rating: f64,
+    pub matches: usize,
+    pub games: usize,
+    pub wins: usize,
+    pub draws: usize,
+}
+
+/// Rates strategies with a three-player Elo: a match counts as one rated
+/// event in which everyone plays both others, a game won by the third one
+/// being a tie between the two losers.
+pub struct Ladder {
+    strategies: Vec<Strategy>,
+    entries: Vec<Entry>,
+    schedule: Schedule,
+    rules: Rules,
+    seed: u64,
+    /// games per match
+    games: usize,
+    threads: usize,
+    played: usize,
+}
+
+impl Ladder {
+    pub fn new(
+        strategies: Vec<Strategy>,
+        schedule: Schedule,
+        rules: Rules,
+        seed: u64,
+        games: usize,
+        threads: usize,
+    ) -> Result<Self> {
+        if strategies.len() < 3 {
+            bail!(
+                "a ladder needs at least 3 strategies, got {}",
+                strategies.len()
+            );
+        }
+        let entries = strategies
+            .iter()
+            .map(|s| Entry {
+                strategy: s.to_string(),
+                rating: INITIAL_RATING,
+                matches: 0,
+                games: 0,
+                wins: 0,
+                draws: 0,
+            })
+            .collect();
+        Ok(Self {
+            strategies,
+            entries,
+            schedule,
+            rules,
+            seed,
+            games,
+            threads,
+            played: 0,
+        })
+    }
+
+    /// the groups of three playing the next round
+    fn tables(&self) -> Vec<[usize; 3]> {
+        let n = self.entries.len();
+        match self.schedule {
+            Schedule::RoundRobin => {
+                let mut tables = vec![];
+                for a in 0..n {
+                    for b in a + 1..n {
+                        for c in b + 1..n {
+                            tables.push([a, b, c]);
+                        }
+                    }
+                }
+                tables
+            }
+            Schedule::Swiss => {
+                let mut order: Vec<usize> = (0..n).collect();
+                order.sort_by(|&a, &b| self.entries[b].rating.total_cmp(&self.entries[a].rating));
+                let mut tables: Vec<[usize; 3]> =
+                    order.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+                // the ones left over play with the lowest rated of the rest
+                if !n.is_multiple_of(3) {
+                    tables.push([order[n - 3], order[n - 2], order[n - 1]]);
+                }
+                tables
+            }
+        }
+    }
+
+    pub fn run(&mut self, rounds: usize) -> Result<()> {
+        for round in 0..rounds {
+            for table in self.tables() {
+                let seats = table.map(|i| self.strategies[i].clone());
+                let seed = sub_seed(self.seed, self.played);
+                let report =
+                    Simulation::new(seats, self.rules, seed).run(self.games, self.threads)?;
+                info!("round {round}: {:?} {:?}", report.seats, report.wins);
+                self.rate(table, &report);
+                self.played += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn rate(&mut self, table: [usize; 3], report: &Report) {
+        if report.games == 0 {
+            return;
+        }
+        let games = report.games as f64;
+        let mut delta = [0.0; 3];
+        for x in 0..3 {
+            for y in 0..3 {
+                if x == y {
+                    continue;
+                }
+                let z = 3 - x - y;
+                let score =
+                    (report.wins[x] as f64 + 0.5 * (report.draws + report.wins[z]) as f64) / games;
+                let diff = self.entries[table[y]].rating - self.entries[table[x]].rating;
+                let expected = 1.0 / (1.0 + 10f64.powf(diff / 400.0));
+                delta[x] += K / 2.0 * (score - expected);
+            }
+        }
+        for (x, &i) in table.iter().enumerate() {
+            let entry = &mut self.entries[i];
+            entry.rating += delta[x];
+            entry.matches += 1;
+            entry.games += report.games;
+            entry.wins += report.wins[x];
+            entry.draws += report.draws;
+        }
+    }
+
+    /// the entries from the highest rating down
+    pub fn leaderboard(&self) -> Vec<Entry> {
+        let mut entries = self.entries.clone();
+        entries.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+        entries
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = "rank,strategy,rating,matches,games,wins,draws\n".to_string();
+        for (rank, e) in self.leaderboard().iter().enumerate() {
+            csv += &format!(
+                "{},{},{:.1},{},{},{},{}\n",
+                rank + 1,
+                e.strategy,
+                e.rating,
+                e.matches,
+                e.games,
+                e.wins,
+                e.draws
+            );
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ladder(n: usize, schedule: Schedule) -> Ladder {
+        let strategies = vec![Strategy::Random; n];
+        Ladder::new(strategies, schedule, Rules::default(), 3, 3, 1).unwrap()
+    }
+
+    #[test]
+    fn test_schedules() {
+        assert_eq!(ladder(4, Schedule::RoundRobin).tables().len(), 4);
+        let tables = ladder(7, Schedule::Swiss).tables();
+        assert_eq!(tables.len(), 3);
+        for i in 0..7 {
+            assert!(tables.iter().any(|t| t.contains(&i)));
+        }
+    }
+
+    #[test]
+    fn test_rating_is_zero_sum() {
+        let mut ladder = ladder(3, Schedule::RoundRobin);
+        let report = Report {
+            seats: [Strategy::Random, Strategy::Random, Strategy::Random],
+            rules: Rules::default(),
+            seed: 0,
+            games: 10,
+            wins: [4, 1, 0],
+            draws: 5,
+            turns: 0,
+            winning_score: 0,
+        };
+        ladder.rate([0, 1, 2], &report);
+        let [a, b, c] = [0, 1, 2].map(|i| ladder.entries[i].rating);
+        assert!(a > b && b > c);
+        assert!((a + b + c - 3.0 * INITIAL_RATING).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use accounts::Accounts;
+use anyhow::Result;
+use dashmap::{mapref::entry::Entry, DashMap};
+use game::Game;
+use history::ReplayStore;
+use matchmaking::Matchmaker;
+use room::{Created, Room, Settings};
+use stats::Stats;
+
+pub mod accounts;
+pub mod agent;
+pub mod card;
+pub mod chat;
+pub mod client;
+pub mod env;
+pub mod eval;
+pub mod external;
+pub mod game;
+pub mod history;
+pub mod ladder;
+pub mod matchmaking;
+pub mod opponent;
+pub mod outbox;
+pub mod record;
+pub mod replay;
+pub mod room;
+pub mod rules;
+pub mod simulate;
+pub mod stats;
+pub mod tune;
+pub mod weights;
+
+pub mod handler;
+
+#[derive(Clone)]
+pub struct GlobalState {
+    rooms: Arc<DashMap<String, Room>>,
+    replays: Arc<dyn ReplayStore>,
+    matchmaker: Arc<Matchmaker>,
+    /// closes any room, from `SHANGDAREN_ADMIN_TOKEN`
+    admin: Option<String>,
+    accounts: Arc<Accounts>,
+    stats: Arc<Stats>,
+    /// how long robots think before they play, from `SHANGDAREN_THINKING_MS`
+    thinking: Duration,
+}
+
+impl Default for GlobalState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GlobalState {
+    pub fn new() -> Self {
+        let accounts = accounts::configured().expect("failed to load the accounts");
+        let stats = stats::configured().expect("failed to load the stats");
+        let state = Self::with_replays(history::configured())
+            .with_accounts(accounts)
+            .with_stats(stats);
+        let state = match std::env::var("SHANGDAREN_THINKING_MS") {
+            Ok(ms) => state.with_thinking(Duration::from_millis(
+                ms.parse()
+                    .expect("SHANGDAREN_THINKING_MS is not a number of milliseconds"),
+            )),
+            Err(_) => state,
+        };
+        match std::env::var("SHANGDAREN_ADMIN_TOKEN") {
+            Ok(token) if !token.is_empty() => state.with_admin(token),
+            _ => state,
+        }
+    }
+
+    pub fn with_replays(replays: Arc<dyn ReplayStore>) -> Self {
+        Self {
+            rooms: Default::default(),
+            replays,
+            matchmaker: Default::default(),
+            admin: None,
+            accounts: Arc::new(Accounts::in_memory()),
+            stats: Arc::new(Stats::in_memory()),
+            thinking: Game::THINKING,
+        }
+    }
+
+    pub fn with_thinking(mut self, thinking: Duration) -> Self {
+        self.thinking = thinking;
+        self
+    }
+
+    pub fn with_stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn with_accounts(mut self, accounts: Arc<Accounts>) -> Self {
+        self.accounts = accounts;
+        self
+    }
+
+    pub fn with_admin(mut self, token: impl Into<String>) -> Self {
+        self.admin = Some(token.into());
+        self
+    }
+
+    pub fn is_admin(&self, token: &str) -> bool {
+        self.admin.as_deref() == Some(token)
+    }
+
+    pub fn with_matchmaker(mut self, matchmaker: Matchmaker) -> Self {
+        self.matchmaker = Arc::new(matchmaker);
+        self
+    }
+
+    /// Create a room under a fresh id.
+    pub fn create_room(&self, settings: &Settings) -> Result<Created> {
+        self.sweep();
+        loop {
+            let id = Room::new_id();
+            let Entry::Vacant(e) = self.rooms.entry(id.clone()) else {
+                continue;
+            };
+            let mut room = Room::new(&id, self, settings)?;
+            let token = room.issue_token();
+            let invite = settings
+                .is_private()
+                .then(|| room.make_private(settings.password.clone()));
+            let info = room.game.info();
+            e.insert(room);
+            return Ok(Created {
+                info,
+                token,
+                invite,
+            });
+        }
+    }
+
+    /// Remove the matched rooms whose players never connected.
+    pub fn sweep(&self) {
+        let now = Instant::now();
+        self.rooms.retain(|_, room| {
+            let abandoned = room.expire(now);
+            if abandoned {
+                room.game.close();
+            }
+            !abandoned
+        });
+    }
+}
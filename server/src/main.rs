@@ -1,29 +1,4 @@
-use std::sync::Arc;
-
-use dashmap::DashMap;
-use handler::socket_handler;
-use room::Room;
-use warp::Filter;
-
-mod agent;
-mod card;
-mod game;
-mod room;
-mod train;
-
-mod handler;
-
-pub struct GlobalState {
-    rooms: Arc<DashMap<String, Room>>,
-}
-
-impl GlobalState {
-    pub fn new() -> Self {
-        Self {
-            rooms: Default::default(),
-        }
-    }
-}
+use server::{handler::routes, GlobalState};
 
 #[tokio::main]
 async fn main() {
@@ -31,9 +6,7 @@ async fn main() {
     // builder.target(env_logger::Target::Stdout);
 
     // builder.init();
-    let w = warp::path!("api" / "ws" / String)
-        .and(warp::ws())
-        .and(warp::any().map(move || GlobalState::new()))
-        .and_then(socket_handler);
-    warp::serve(w).run(([0, 0, 0, 0], 3131)).await
+    warp::serve(routes(GlobalState::new()))
+        .run(([0, 0, 0, 0], 3131))
+        .await
 }
@@ -0,0 +1,430 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use futures::{SinkExt, StreamExt};
+use log::warn;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use warp::filters::ws::{Message, WebSocket};
+
+use crate::{
+    agent::Strategy,
+    room::{RobotSettings, Settings},
+    rules::Rules,
+    GlobalState,
+};
+
+/// Who can be seated together.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Pool {
+    pub rules: Rules,
+    /// a skill bracket, players without one only meet each other
+    pub bracket: Option<String>,
+}
+
+/// What a player asks for when clicking "play now".
+#[derive(Deserialize, Default, Debug)]
+pub struct MatchQuery {
+    /// a rule preset, `standard` when missing
+    pub rules: Option<String>,
+    pub bracket: Option<String>,
+    /// the robots filling the table when nobody else shows up
+    pub robots: Option<Strategy>,
+}
+
+impl MatchQuery {
+    pub fn pool(&self) -> Result<Pool> {
+        Ok(Pool {
+            rules: match &self.rules {
+                Some(preset) => preset.parse()?,
+                None => Rules::default(),
+            },
+            bracket: self.bracket.clone(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub enum MatchMessage {
+    /// waiting for a table, with `waiting` players in the pool
+    Queued { waiting: usize },
+    /// a table was formed, connect to the room and hand over the
+    /// reservation in the `Hello`
+    Matched { room: String, reservation: String },
+}
+
+impl From<MatchMessage> for Message {
+    fn from(value: MatchMessage) -> Self {
+        Message::text(serde_json::to_string(&value).expect("failed to serialize"))
+    }
+}
+
+/// A seat kept for a matched player.
+#[derive(Debug)]
+pub struct Reserved {
+    pub room: String,
+    pub reservation: String,
+}
+
+struct Ticket {
+    id: u64,
+    robots: Strategy,
+    room: oneshot::Sender<Reserved>,
+}
+
+/// Players waiting to be seated, by pool.
+pub struct Matchmaker {
+    /// how long a player waits for others before robots fill the table
+    pub wait: Duration,
+    /// how long a matched room keeps the seats of players who don't connect
+    pub hold: Duration,
+    queues: Mutex<HashMap<Pool, Vec<Ticket>>>,
+    next: AtomicU64,
+}
+
+impl Default for Matchmaker {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(20))
+    }
+}
+
+impl Matchmaker {
+    pub fn new(wait: Duration) -> Self {
+        Self {
+            wait,
+            hold: Duration::from_secs(60),
+            queues: Default::default(),
+            next: AtomicU64::new(0),
+        }
+    }
+
+    pub fn waiting(&self, pool: &Pool) -> usize {
+        self.queues.lock().get(pool).map_or(0, |q| q.len())
+    }
+
+    /// Queue a player, who gets a seat in a room once a table is formed.
+    ///
+    /// The third player in a pool completes a table right away.
+    pub fn join(
+        &self,
+        state: &GlobalState,
+        pool: &Pool,
+        robots: Strategy,
+    ) -> Result<(u64, oneshot::Receiver<Reserved>)> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        let table = {
+            let mut queues = self.queues.lock();
+            let queue = queues.entry(pool.clone()).or_default();
+            queue.push(Ticket {
+                id,
+                robots,
+                room: tx,
+            });
+            if queue.len() < 3 {
+                return Ok((id, rx));
+            }
+            let table: Vec<_> = queue.drain(..3).collect();
+            if queue.is_empty() {
+                queues.remove(pool);
+            }
+            table
+        };
+        self.seat(state, pool, table, None)?;
+        Ok((id, rx))
+    }
+
+    pub fn leave(&self, pool: &Pool, ticket: u64) {
+        let mut queues = self.queues.lock();
+        if let Some(queue) = queues.get_mut(pool) {
+            queue.retain(|t| t.id != ticket);
+            if queue.is_empty() {
+                queues.remove(pool);
+            }
+        }
+    }
+
+    /// Seat a player who waited long enough with whoever else is waiting,
+    /// filling the table with the robots they asked for.
+    pub fn top_up(&self, state: &GlobalState, pool: &Pool, ticket: u64) -> Result<()> {
+        let table = {
+            let mut queues = self.queues.lock();
+            let Some(queue) = queues.get_mut(pool) else {
+                return Ok(());
+            };
+            let Some(i) = queue.iter().position(|t| t.id == ticket) else {
+                return Ok(());
+            };
+            // the oldest ones keep their place at the table
+            let mine = queue.remove(i);
+            let mut table: Vec<_> = queue.drain(..queue.len().min(2)).collect();
+            table.insert(0, mine);
+            if queue.is_empty() {
+                queues.remove(pool);
+            }
+            table
+        };
+        let robots = table[0].robots.clone();
+        self.seat(state, pool, table, Some(robots))
+    }
+
+    fn seat(
+        &self,
+        state: &GlobalState,
+        pool: &Pool,
+        table: Vec<Ticket>,
+        robots: Option<Strategy>,
+    ) -> Result<()> {
+        let settings = Settings {
+            rules: pool.rules,
+            robots: (table.len()..3)
+                .map(|_| RobotSettings {
+                    strategy: robots.clone(),
+                    difficulty: Default::default(),
+                    name: None,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let id = state.create_room(&settings)?.info.id;
+        let reservations = match state.rooms.get(&id) {
+            Some(room) => room.reserve(table.len(), self.hold),
+            None => bail!("room {id} was closed right away"),
+        };
+        for (ticket, reservation) in table.into_iter().zip(reservations) {
+            // a player who left in the meantime keeps the seat until the
+            // reservation runs out
+            let seat = Reserved {
+                room: id.clone(),
+                reservation,
+            };
+            ticket.room.send(seat).ok();
+        }
+        Ok(())
+    }
+
+    /// Keep a player queued until they are matched or hang up.
+    pub async fn on_connection(
+        &self,
+        state: &GlobalState,
+        pool: Pool,
+        robots: Strategy,
+        socket: WebSocket,
+    ) {
+        let (ticket, room) = match self.join(state, &pool, robots) {
+            Ok(joined) => joined,
+            Err(e) => {
+                warn!("failed to form a table: {e:#}");
+                return;
+            }
+        };
+        if let Err(e) = self.wait_match(state, &pool, ticket, room, socket).await {
+            warn!("matchmaking terminated because of {e:#}");
+        }
+        self.leave(&pool, ticket);
+    }
+
+    async fn wait_match(
+        &self,
+        state: &GlobalState,
+        pool: &Pool,
+        ticket: u64,
+        mut room: oneshot::Receiver<Reserved>,
+        mut socket: WebSocket,
+    ) -> Result<()> {
+        let waiting = self.waiting(pool);
+        socket.send(MatchMessage::Queued { waiting }.into()).await?;
+        let timeout = tokio::time::sleep(self.wait);
+        tokio::pin!(timeout);
+        let mut topped_up = false;
+        loop {
+            tokio::select! {
+                seat = &mut room => {
+                    let Reserved { room, reservation } =
+                        seat.context("left the queue without a table")?;
+                    socket.send(MatchMessage::Matched { room, reservation }.into()).await?;
+                    socket.close().await.ok();
+                    return Ok(());
+                }
+                _ = &mut timeout, if !topped_up => {
+                    topped_up = true;
+                    self.top_up(state, pool, ticket)?;
+                }
+                message = socket.next() => {
+                    match message {
+                        None => return Ok(()),
+                        Some(message) => {
+                            message?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{history::MemoryStore, room::User};
+
+    use super::*;
+
+    fn pool(bracket: Option<&str>) -> Pool {
+        Pool {
+            rules: Rules::CASUAL,
+            bracket: bracket.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_table() {
+        let state = GlobalState::with_replays(Arc::new(MemoryStore::new(1)));
+        let matchmaker = Matchmaker::default();
+        let mut rooms = vec![];
+        for _ in 0..2 {
+            rooms.push(
+                matchmaker
+                    .join(&state, &pool(None), Strategy::Random)
+                    .unwrap()
+                    .1,
+            );
+        }
+        // another bracket waits on its own
+        let (_, mut other) = matchmaker
+            .join(&state, &pool(Some("beginner")), Strategy::Random)
+            .unwrap();
+        assert_eq!(matchmaker.waiting(&pool(None)), 2);
+        rooms.push(
+            matchmaker
+                .join(&state, &pool(None), Strategy::Random)
+                .unwrap()
+                .1,
+        );
+        assert_eq!(matchmaker.waiting(&pool(None)), 0);
+
+        let ids: Vec<_> = rooms
+            .iter_mut()
+            .map(|r| r.try_recv().unwrap().room)
+            .collect();
+        assert!(ids.iter().all(|id| *id == ids[0]));
+        let info = state.rooms.get(&ids[0]).unwrap().info();
+        assert_eq!(info.rules, Rules::CASUAL);
+        assert!(info.seats.is_empty());
+        assert_eq!(info.reserved, 3);
+        assert!(!info.is_open());
+        assert!(other.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_top_up() {
+        let state = GlobalState::with_replays(Arc::new(MemoryStore::new(1)));
+        let matchmaker = Matchmaker::default();
+        let (first, mut room) = matchmaker
+            .join(&state, &pool(None), Strategy::Random)
+            .unwrap();
+        let (second, _) = matchmaker
+            .join(&state, &pool(None), Strategy::Level1)
+            .unwrap();
+        matchmaker.leave(&pool(None), second);
+        matchmaker.top_up(&state, &pool(None), first).unwrap();
+        assert_eq!(matchmaker.waiting(&pool(None)), 0);
+
+        let id = room.try_recv().unwrap().room;
+        let info = state.rooms.get(&id).unwrap().info();
+        assert_eq!(info.seats.len(), 2);
+        assert!(info
+            .seats
+            .iter()
+            .all(|s| s.strategy == Some(Strategy::Random)));
+        // a player already seated is not seated twice
+        matchmaker.top_up(&state, &pool(None), first).unwrap();
+        assert_eq!(state.rooms.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reserved_seats() {
+        let state = GlobalState::with_replays(Arc::new(MemoryStore::new(1)));
+        let matchmaker = Matchmaker {
+            hold: Duration::ZERO,
+            ..Default::default()
+        };
+        let (first, mut room) = matchmaker
+            .join(&state, &pool(None), Strategy::Random)
+            .unwrap();
+        matchmaker.top_up(&state, &pool(None), first).unwrap();
+        let seat = room.try_recv().unwrap();
+        let room = state.rooms.get(&seat.room).unwrap().clone();
+        assert_eq!(room.info().reserved, 1);
+
+        // strangers can't take the seat, the matched player can
+        assert!(room.add_user(User::new("eve", "eve"), None).await.is_err());
+        assert!(room
+            .add_user(User::new("eve", "eve"), Some("guess"))
+            .await
+            .is_err());
+        let (seat_no, _rx) = room
+            .add_user(User::new("alice", "alice"), Some(&seat.reservation))
+            .await
+            .unwrap();
+        assert_eq!(seat_no, 2);
+        assert_eq!(room.info().reserved, 0);
+        // the room is taken, so it stays
+        state.sweep();
+        assert_eq!(state.rooms.len(), 1);
+
+        // a room nobody connected to is gone once the hold runs out
+        let (first, mut other) = matchmaker
+            .join(&state, &pool(None), Strategy::Random)
+            .unwrap();
+        matchmaker.top_up(&state, &pool(None), first).unwrap();
+        let other = other.try_recv().unwrap().room;
+        state.sweep();
+        assert!(state.rooms.get(&other).is_none());
+        assert!(state.rooms.get(&seat.room).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_racing_joins() {
+        let state = GlobalState::with_replays(Arc::new(MemoryStore::new(1)));
+        let matchmaker = Matchmaker {
+            hold: Duration::ZERO,
+            ..Default::default()
+        };
+        let (first, mut room) = matchmaker
+            .join(&state, &pool(None), Strategy::Random)
+            .unwrap();
+        matchmaker.top_up(&state, &pool(None), first).unwrap();
+        let seat = room.try_recv().unwrap();
+        let room = state.rooms.get(&seat.room).unwrap().clone();
+
+        // the same player connecting twice at once sits once
+        let alice = User::new("alice", "alice");
+        let (a, b) = tokio::join!(
+            room.add_user(alice.clone(), Some(&seat.reservation)),
+            room.add_user(alice.clone(), Some(&seat.reservation)),
+        );
+        assert!(a.is_ok() != b.is_ok());
+        assert_eq!(room.users().len(), 1);
+
+        // a reservation is kept when the table could not seat its holder
+        let (first, mut other) = matchmaker
+            .join(&state, &pool(None), Strategy::Random)
+            .unwrap();
+        matchmaker.top_up(&state, &pool(None), first).unwrap();
+        let seat = other.try_recv().unwrap();
+        let room = state.rooms.get(&seat.room).unwrap().clone();
+        room.game.close();
+        assert!(room
+            .add_user(User::new("bob", "bob"), Some(&seat.reservation))
+            .await
+            .is_err());
+        assert_eq!(room.info().reserved, 1);
+    }
+}
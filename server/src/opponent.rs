@@ -0,0 +1,125 @@
+use crate::card::{Card, Pairing};
+
+/// how much a discarded kind is still wanted by the one who discarded it
+const DISCARDED: f32 = 0.3;
+/// how much the other kinds of a discarded category are still wanted
+const DISCARDED_CATEGORY: f32 = 0.8;
+/// how much more the kinds next to a claimed one are wanted
+const CLAIMED_CATEGORY: f32 = 1.2;
+/// 上大人 and the jing category score more, so everyone collects them
+const VALUABLE_CATEGORY: f32 = 1.2;
+/// a Pao gives the neighbour a pairing worth more and an extra draw
+const PAO_WEIGHT: f32 = 2.0;
+
+/// What a robot knows about one of its neighbours.
+pub struct Opponent<'a> {
+    pub out: &'a [Card],
+    pub pairing: &'a [Pairing],
+}
+
+impl Opponent<'_> {
+    /// Estimate how likely the neighbour collects each kind, 1.0 being a
+    /// kind we know nothing about.
+    pub fn need(&self, jing: Card) -> [f32; 24] {
+        let mut need = [1.0; 24];
+        for cat in [0, jing.0 / 12] {
+            for kind in cat * 3..cat * 3 + 3 {
+                need[kind as usize] = VALUABLE_CATEGORY;
+            }
+        }
+        for c in self.out {
+            let kind = c.0 / 4;
+            let cat = kind / 3;
+            for k in cat * 3..cat * 3 + 3 {
+                need[k as usize] *= if k == kind {
+                    DISCARDED
+                } else {
+                    DISCARDED_CATEGORY
+                };
+            }
+        }
+        for p in self.pairing {
+            let kind = match p {
+                Pairing::Triplet(c) | Pairing::Quadlet(c) => c.0 / 4,
+            };
+            let cat = kind / 3;
+            for k in cat * 3..cat * 3 + 3 {
+                need[k as usize] *= if k == kind { 0.0 } else { CLAIMED_CATEGORY };
+            }
+        }
+        need
+    }
+
+    /// cards the neighbour holds while waiting for its turn
+    pub fn hand_size(&self) -> u8 {
+        19 - 3 * self.pairing.len() as u8
+    }
+
+    /// Estimate the risk that discarding a card of `kind` feeds this
+    /// neighbour a Ding or a Pao.
+    ///
+    /// `unseen` is the number of cards of `kind` we have not seen and
+    /// `unseen_total` the number of all unseen cards, i.e. both neighbours'
+    /// hands and the wall.
+    pub fn danger(&self, kind: u8, jing: Card, unseen: u8, unseen_total: u8) -> f32 {
+        let hand = self.hand_size();
+        let ding = hold_at_least(2, unseen, unseen_total, hand);
+        let pao = hold_at_least(3, unseen, unseen_total, hand);
+        // Hu from a discard is not part of the rules, so a discard can only
+        // help the neighbour through a claim
+        self.need(jing)[kind as usize] * (ding + PAO_WEIGHT * pao)
+    }
+}
+
+/// Probability that a hand of `hand` cards drawn from `total` unseen cards
+/// holds at least `n` of the `kind` cards of one kind.
+fn hold_at_least(n: u8, kind: u8, total: u8, hand: u8) -> f32 {
+    if kind < n || total == 0 {
+        return 0.0;
+    }
+    let hand = hand.min(total);
+    let mut acc = 0.0;
+    for x in n..=kind.min(hand) {
+        acc += choose(kind, x) * choose(total - kind, hand - x) / choose(total, hand);
+    }
+    acc as f32
+}
+
+fn choose(n: u8, k: u8) -> f64 {
+    if k > n {
+        return 0.0;
+    }
+    let k = k.min(n - k);
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discarded_kind_is_less_needed() {
+        let out = [Card(13)];
+        let opponent = Opponent {
+            out: &out,
+            pairing: &[],
+        };
+        let need = opponent.need(Card(95));
+        assert!(need[3] < need[4]);
+        assert!(need[4] < need[7]);
+    }
+
+    #[test]
+    fn test_danger() {
+        let opponent = Opponent {
+            out: &[],
+            pairing: &[],
+        };
+        // every other card of the kind has been seen
+        assert_eq!(opponent.danger(7, Card(95), 1, 50), 0.0);
+        let few = opponent.danger(7, Card(95), 2, 50);
+        let many = opponent.danger(7, Card(95), 3, 50);
+        assert!(few > 0.0);
+        assert!(few < many);
+    }
+}
@@ -0,0 +1,149 @@
+use log::warn;
+use parking_lot::Mutex;
+use tokio::sync::{
+    broadcast,
+    mpsc::{self, error::TrySendError},
+};
+
+use crate::game::ServerMessage;
+
+/// The messages for one seat, in order.
+pub type Inbox = mpsc::Receiver<ServerMessage>;
+
+/// Where the messages of a room go: a bounded queue per seat carrying only
+/// what the seat may see, and a fan-out of what everyone sees for the
+/// spectators.
+///
+/// A seat whose queue fills up is too far behind to catch up. Its queue is
+/// closed, which hangs up on it and leaves the seat to a stand-in. A
+/// spectator who falls behind skips ahead to a snapshot of the room.
+pub struct Outbox {
+    seats: Mutex<Vec<Option<mpsc::Sender<ServerMessage>>>>,
+    public: broadcast::Sender<ServerMessage>,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self {
+            seats: Mutex::new(vec![None, None, None]),
+            public: broadcast::channel(Self::PUBLIC_QUEUE).0,
+        }
+    }
+}
+
+impl Outbox {
+    /// what a seat may have waiting, more than a hand sends it
+    pub const SEAT_QUEUE: usize = 256;
+    pub const PUBLIC_QUEUE: usize = 64;
+
+    /// Open the queue of `seat`, replacing the one of whoever sat there.
+    pub fn open(&self, seat: u8) -> Inbox {
+        let (tx, rx) = mpsc::channel(Self::SEAT_QUEUE);
+        self.seats.lock()[seat as usize] = Some(tx);
+        rx
+    }
+
+    /// Stop queueing for `seat`, whose player left.
+    pub fn close(&self, seat: u8) {
+        self.seats.lock()[seat as usize] = None;
+    }
+
+    /// what is sent to every seat, from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerMessage> {
+        self.public.subscribe()
+    }
+
+    /// Queue `msg` for the seat it is addressed to, or for every seat and
+    /// the spectators.
+    pub fn send(&self, msg: ServerMessage) {
+        let mut seats = self.seats.lock();
+        match msg.to() {
+            Some(seat) => Self::deliver(&mut seats, seat, msg),
+            None => {
+                for seat in 0..seats.len() as u8 {
+                    Self::deliver(&mut seats, seat, msg.clone());
+                }
+                // nobody watching is fine
+                self.public.send(msg).ok();
+            }
+        }
+    }
+
+    fn deliver(seats: &mut [Option<mpsc::Sender<ServerMessage>>], seat: u8, msg: ServerMessage) {
+        let Some(queue) = seats.get_mut(seat as usize) else {
+            return;
+        };
+        let Some(tx) = queue else {
+            return;
+        };
+        match tx.try_send(msg) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                warn!(
+                    "seat {seat} fell {} messages behind, hanging up",
+                    Self::SEAT_QUEUE
+                );
+                *queue = None;
+            }
+            Err(TrySendError::Closed(_)) => *queue = None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closed(to: Option<u8>) -> ServerMessage {
+        ServerMessage::Closed { to }
+    }
+
+    #[test]
+    fn test_private_messages_stay_private() {
+        let outbox = Outbox::default();
+        let mut alice = outbox.open(0);
+        let mut bob = outbox.open(1);
+        let mut public = outbox.subscribe();
+        outbox.send(closed(Some(1)));
+        outbox.send(closed(None));
+        assert!(matches!(
+            alice.try_recv(),
+            Ok(ServerMessage::Closed { to: None })
+        ));
+        assert!(alice.try_recv().is_err());
+        assert!(matches!(
+            bob.try_recv(),
+            Ok(ServerMessage::Closed { to: Some(1) })
+        ));
+        assert!(matches!(
+            bob.try_recv(),
+            Ok(ServerMessage::Closed { to: None })
+        ));
+        assert!(matches!(
+            public.try_recv(),
+            Ok(ServerMessage::Closed { to: None })
+        ));
+        assert!(public.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_lagging_seats_are_hung_up_on() {
+        let outbox = Outbox::default();
+        let mut alice = outbox.open(0);
+        let mut bob = outbox.open(1);
+        for _ in 0..Outbox::SEAT_QUEUE {
+            outbox.send(closed(None));
+            bob.try_recv().unwrap();
+        }
+        // alice read nothing, one more is too many
+        outbox.send(closed(None));
+        for _ in 0..Outbox::SEAT_QUEUE {
+            alice.try_recv().unwrap();
+        }
+        assert!(matches!(
+            alice.try_recv(),
+            Err(mpsc::error::TryRecvError::Disconnected)
+        ));
+        assert!(bob.try_recv().is_ok());
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::{Decision, Difficulty, Strategy},
+    rules::Rules,
+    weights::Weights,
+};
+
+/// One decision of a self-play game, a line of the dataset.
+///
+/// Seating `seats` with `rules` and calling `GameState::seed` with `seed`
+/// before the deal plays the same game again.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Sample {
+    pub seed: u64,
+    /// the strategy of each seat
+    pub seats: [Strategy; 3],
+    pub rules: Rules,
+    /// the seat that made the decision
+    pub seat: u8,
+    /// what the seat decided with
+    #[serde(default)]
+    pub weights: Weights,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    #[serde(flatten)]
+    pub decision: Decision,
+    /// the seat that won the hand, `None` for a draw
+    pub winner: Option<u8>,
+    pub winning_score: Option<u8>,
+}
+
+/// Writes samples as JSON lines, shared by the simulation threads.
+pub struct Recorder {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("failed to create {path}"))?;
+        Ok(Self {
+            out: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Write the samples of a game, which stay together in the file.
+    pub fn write(&self, samples: &[Sample]) -> Result<()> {
+        let mut out = self.out.lock();
+        for sample in samples {
+            serde_json::to_writer(&mut *out, sample)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.out.lock().flush()?;
+        Ok(())
+    }
+}
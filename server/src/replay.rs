@@ -0,0 +1,427 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::Strategy,
+    card::{Card, Pairing},
+    eval,
+    game::GameState,
+    rules::{self, Rules},
+};
+
+/// the replay format written by this version, bumped on any change that
+/// older readers cannot parse
+pub const VERSION: u32 = 1;
+
+/// Who sat at a seat.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct Seat {
+    pub name: String,
+    /// the strategy of a robot, `None` for a human
+    pub strategy: Option<Strategy>,
+}
+
+/// Something that happened during a hand, in the order it happened.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Event {
+    Draw {
+        seat: u8,
+        card: Card,
+    },
+    Discard {
+        seat: u8,
+        card: Card,
+    },
+    Ding {
+        seat: u8,
+        card: Card,
+    },
+    Pao {
+        seat: u8,
+        card: Card,
+    },
+    /// a Ding or a Pao offered to `seat` and turned down
+    Decline {
+        seat: u8,
+        pairing: Pairing,
+    },
+    Hu {
+        seat: u8,
+        score: Option<u8>,
+    },
+    /// the hand ended without a winner
+    End,
+}
+
+/// A recorded hand: the deal and every event up to the result.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct Replay {
+    pub version: u32,
+    /// `GameState::seed` with it before the deal plays the same hand again
+    pub seed: u64,
+    /// unix time of the deal, in seconds
+    pub started: u64,
+    #[serde(default)]
+    pub room: String,
+    /// played in a private room, left out of the listings
+    #[serde(default)]
+    pub private: bool,
+    pub rules: Rules,
+    pub seats: Vec<Seat>,
+    pub jing: Card,
+    /// the seat playing first
+    pub dealer: u8,
+    pub hands: Vec<Vec<Card>>,
+    /// the cards left after the deal, drawn from the end
+    pub wall: Vec<Card>,
+    pub events: Vec<Event>,
+}
+
+/// read first, to refuse a replay of an unknown version before parsing it
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+impl Replay {
+    /// the last event, once the hand is over
+    pub fn result(&self) -> Option<Event> {
+        self.events
+            .last()
+            .copied()
+            .filter(|e| matches!(e, Event::Hu { .. } | Event::End))
+    }
+
+    pub fn winner(&self) -> Option<u8> {
+        match self.result() {
+            Some(Event::Hu { seat, .. }) => Some(seat),
+            _ => None,
+        }
+    }
+
+    pub fn id(&self) -> String {
+        format!("{}-{:016x}", self.started, self.seed)
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.json", self.id())
+    }
+
+    /// Write the replay into `dir` and return its path.
+    pub fn save(&self, dir: &Path) -> Result<PathBuf> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let path = dir.join(self.file_name());
+        std::fs::write(&path, serde_json::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let header: Header = serde_json::from_str(content)?;
+        if header.version != VERSION {
+            bail!(
+                "unsupported replay version {}, expect {VERSION}",
+                header.version
+            );
+        }
+        Ok(serde_json::from_str(content)?)
+    }
+
+    /// The table after the first `step` events, checking that they were
+    /// legal.
+    pub fn position(&self, step: usize) -> Result<Position> {
+        if step > self.events.len() {
+            bail!("step {step} is past the {} events", self.events.len());
+        }
+        let mut position = Position::deal(self)?;
+        for _ in 0..step {
+            position.apply(self)?;
+        }
+        Ok(position)
+    }
+
+    /// Check that every event was legal and that the hand ended the way
+    /// the rules say it should have.
+    pub fn verify(&self) -> Result<()> {
+        let position = self.position(self.events.len())?;
+        if position.expect != Expect::Over {
+            bail!("the hand stops at step {}", position.step);
+        }
+        Ok(())
+    }
+}
+
+/// What the next event has to be.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Expect {
+    Draw(u8),
+    Discard(u8),
+    /// the answer of a seat to the claim it was offered
+    Claim(u8, Pairing),
+    Hu(u8),
+    Over,
+}
+
+/// The table at some step of a replay, rebuilt from the deal.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Position {
+    pub hands: Vec<Vec<Card>>,
+    pub outs: Vec<Vec<Card>>,
+    pub pairings: Vec<Vec<Pairing>>,
+    pub wall: Vec<Card>,
+    /// the events applied so far
+    pub step: usize,
+    expect: Expect,
+}
+
+impl Position {
+    fn deal(replay: &Replay) -> Result<Self> {
+        if replay.hands.len() != 3 || replay.seats.len() != 3 {
+            bail!("expect 3 seats, got {}", replay.hands.len());
+        }
+        let mut cards: Vec<Card> = replay.hands.concat();
+        cards.extend(&replay.wall);
+        cards.sort();
+        if !cards.iter().map(|c| c.0 as usize).eq(0..GameState::TOTAL) {
+            bail!("the deal is not a full set of cards");
+        }
+        Ok(Self {
+            hands: replay.hands.clone(),
+            outs: vec![vec![]; 3],
+            pairings: vec![vec![]; 3],
+            wall: replay.wall.clone(),
+            step: 0,
+            expect: Expect::Draw(replay.dealer),
+        })
+    }
+
+    /// the seat expected to play next, `None` once the hand is over
+    pub fn turn(&self) -> Option<u8> {
+        match self.expect {
+            Expect::Draw(seat) | Expect::Discard(seat) | Expect::Claim(seat, _) => Some(seat),
+            Expect::Hu(seat) => Some(seat),
+            Expect::Over => None,
+        }
+    }
+
+    fn count(&self, seat: u8, card: Card) -> usize {
+        self.hands[seat as usize]
+            .iter()
+            .filter(|c| c.is_same_kind(&card))
+            .count()
+    }
+
+    fn is_hu(&self, seat: u8, replay: &Replay) -> bool {
+        let seat = seat as usize;
+        let score = self.pairings[seat].iter().map(|p| p.score()).sum();
+        GameState::is_hu(
+            &self.hands[seat],
+            score,
+            replay.jing,
+            replay.rules.min_hu_score,
+        )
+    }
+
+    /// Move the claimed discard and the matching cards of `seat` into a
+    /// pairing.
+    fn take(&mut self, seat: u8, pairing: Pairing, discarder: u8) {
+        let (Pairing::Triplet(card) | Pairing::Quadlet(card)) = pairing;
+        self.outs[discarder as usize].pop();
+        self.hands[seat as usize].retain(|c| !c.is_same_kind(&card));
+        self.pairings[seat as usize].push(pairing);
+    }
+
+    /// Play the next event of `replay`.
+    pub fn apply(&mut self, replay: &Replay) -> Result<()> {
+        let Some(&event) = replay.events.get(self.step) else {
+            bail!("no event after step {}", self.step);
+        };
+        let step = self.step;
+        let illegal =
+            || anyhow::anyhow!("step {step}: {event:?} while expecting {:?}", self.expect);
+        self.expect = match (self.expect, event) {
+            (Expect::Draw(_), Event::End) if self.wall.is_empty() => Expect::Over,
+            (Expect::Draw(s), Event::Draw { seat, card }) if s == seat => {
+                if self.wall.pop() != Some(card) {
+                    bail!("step {step}: {event:?} is not the next card of the wall");
+                }
+                self.hands[seat as usize].push(card);
+                if self.is_hu(seat, replay) {
+                    Expect::Hu(seat)
+                } else {
+                    Expect::Discard(seat)
+                }
+            }
+            (Expect::Discard(s), Event::Discard { seat, card }) if s == seat => {
+                let hand = &mut self.hands[seat as usize];
+                let Some(i) = hand.iter().position(|&c| c == card) else {
+                    bail!("step {step}: seat {seat} does not hold {card:?}");
+                };
+                hand.remove(i);
+                self.outs[seat as usize].push(card);
+                match replay.rules.claim(seat, card, |s| self.count(s, card)) {
+                    Some((claimer, pairing)) => Expect::Claim(claimer, pairing),
+                    None => Expect::Draw(rules::next_seat(seat)),
+                }
+            }
+            (Expect::Claim(s, pairing @ Pairing::Triplet(c)), Event::Ding { seat, card })
+                if s == seat && c == card =>
+            {
+                self.take(seat, pairing, self.discarder(seat, card)?);
+                Expect::Discard(seat)
+            }
+            (Expect::Claim(s, pairing @ Pairing::Quadlet(c)), Event::Pao { seat, card })
+                if s == seat && c == card =>
+            {
+                self.take(seat, pairing, self.discarder(seat, card)?);
+                Expect::Draw(seat)
+            }
+            (Expect::Claim(s, p), Event::Decline { seat, pairing })
+                if s == seat && p == pairing =>
+            {
+                let (Pairing::Triplet(card) | Pairing::Quadlet(card)) = pairing;
+                Expect::Draw(rules::next_seat(self.discarder(seat, card)?))
+            }
+            (Expect::Hu(s), Event::Hu { seat, score }) if s == seat => {
+                let hand = &self.hands[seat as usize];
+                let value = eval::evaluate(
+                    hand,
+                    &self.pairings[seat as usize],
+                    replay.jing,
+                    None,
+                    replay.rules.min_hu_score,
+                );
+                if score != value.map(|v| v.score) {
+                    bail!("step {step}: the hand of seat {seat} scores {value:?}, not {score:?}");
+                }
+                Expect::Over
+            }
+            _ => return Err(illegal()),
+        };
+        self.step += 1;
+        Ok(())
+    }
+
+    /// the seat whose last discard `card` is, claimed by `seat`
+    fn discarder(&self, seat: u8, card: Card) -> Result<u8> {
+        [(seat + 2) % 3, (seat + 1) % 3]
+            .into_iter()
+            .find(|&s| self.outs[s as usize].last() == Some(&card))
+            .context("the claimed card is not the last discard")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::agent::{Difficulty, Strategy};
+
+    use super::*;
+
+    fn play(seed: u64) -> Replay {
+        play_with(seed, Rules::default())
+    }
+
+    fn play_with(seed: u64, rules: Rules) -> Replay {
+        let mut game = GameState::default();
+        game.set_rules(rules);
+        for _ in 0..3 {
+            game.add_robot(Some(Strategy::Level1), Difficulty::Hard, None)
+                .unwrap();
+        }
+        game.seed(seed);
+        game.start().unwrap();
+        while !game.is_over() {
+            if let Some(card) = game.robot_turn(None) {
+                game.next_turn(&card);
+            }
+        }
+        game.replay().unwrap().clone()
+    }
+
+    #[test]
+    fn test_replay_records_the_hand() {
+        let replay = play(7);
+        assert_eq!(replay.seed, 7);
+        assert_eq!(replay.hands.len(), 3);
+        assert!(replay.hands.iter().all(|h| h.len() == 19));
+        let dealt = replay.hands.iter().map(Vec::len).sum::<usize>() + replay.wall.len();
+        assert_eq!(dealt, GameState::TOTAL);
+        let draws = replay
+            .events
+            .iter()
+            .filter(|e| matches!(e, Event::Draw { .. }))
+            .count();
+        assert!(draws > 0 && draws <= replay.wall.len());
+        assert!(replay.result().is_some());
+        let mut again = play(7);
+        again.started = replay.started;
+        assert_eq!(replay, again);
+    }
+
+    #[test]
+    fn test_recorded_hands_verify() {
+        for seed in 0..10 {
+            play(seed).verify().unwrap();
+        }
+        // a hand won by seat 1
+        let replay = play_with(11, Rules::CASUAL);
+        replay.verify().unwrap();
+        assert_eq!(replay.winner(), Some(1));
+
+        let mut missing = replay.clone();
+        missing.events.pop();
+        assert!(missing.verify().is_err());
+        let mut wrong_score = replay.clone();
+        if let Some(Event::Hu { score, .. }) = wrong_score.events.last_mut() {
+            *score = Some(0);
+        }
+        assert!(wrong_score.verify().is_err());
+        let mut wrong_draw = replay.clone();
+        wrong_draw.events[0] = Event::Draw {
+            seat: replay.dealer,
+            card: replay.wall[0],
+        };
+        assert!(wrong_draw.verify().is_err());
+    }
+
+    #[test]
+    fn test_position_steps() {
+        let replay = play(5);
+        let start = replay.position(0).unwrap();
+        assert_eq!(start.hands, replay.hands);
+        assert_eq!(start.turn(), Some(replay.dealer));
+        let end = replay.position(replay.events.len()).unwrap();
+        assert_eq!(end.turn(), None);
+        let mut position = replay.position(0).unwrap();
+        for step in 1..=replay.events.len() {
+            position.apply(&replay).unwrap();
+            assert_eq!(position, replay.position(step).unwrap());
+        }
+        assert!(replay.position(replay.events.len() + 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_checks_the_version() {
+        let mut replay = play(3);
+        assert_eq!(
+            Replay::parse(&serde_json::to_string(&replay).unwrap()).unwrap(),
+            replay
+        );
+        let dir = std::env::temp_dir().join("shangdaren-replays");
+        let path = replay.save(&dir).unwrap();
+        assert_eq!(Replay::load(&path).unwrap(), replay);
+        std::fs::remove_file(path).unwrap();
+
+        replay.version = VERSION + 1;
+        assert!(Replay::parse(&serde_json::to_string(&replay).unwrap()).is_err());
+    }
+}
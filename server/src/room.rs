@@ -1,37 +1,410 @@
 #![allow(unused)]
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use dashmap::DashSet;
+use anyhow::{bail, Context, Result};
+use dashmap::DashMap;
+use futures::StreamExt;
+use log::warn;
+use parking_lot::Mutex;
 
-use crate::game::Game;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use warp::ws::WebSocket;
 
+use crate::{
+    accounts::Profile,
+    agent::{Difficulty, Strategy},
+    card::{Card, Pairing},
+    game::{ClientMessage, Game, Mode},
+    outbox::Inbox,
+    rules::Rules,
+    GlobalState,
+};
+
+#[derive(Clone)]
 pub struct Room {
-    users: Arc<DashSet<User>>,
+    /// the humans at the table and their seats
+    users: Arc<DashMap<User, u8>>,
     pub game: Arc<Game>,
+    /// what the creator closes the room with, rooms opened by a link have none
+    token: Option<String>,
+    /// seats kept for matched players until they connect
+    reservations: Arc<Mutex<Reservations>>,
+    /// held while someone sits down, so two can't take the same seat
+    seating: Arc<tokio::sync::Mutex<()>>,
+    /// who gets into a private room, public rooms have none
+    access: Option<Access>,
+}
+
+#[derive(Clone)]
+struct Access {
+    invite: String,
+    password: Option<String>,
+}
+
+#[derive(Default)]
+struct Reservations {
+    tokens: Vec<String>,
+    /// when the seats still reserved are given up
+    until: Option<Instant>,
 }
 
-#[derive(PartialEq, Eq, Hash)]
+/// Someone who said hello, `id` is stable while `name` is shown to others.
+///
+/// Players with an account go by their login, guests by `guest:` and the id
+/// they sent, so a guest can't pass for someone registered.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct User {
-    name: String,
+    pub id: String,
+    pub name: String,
 }
 
 impl User {
-    pub fn new(name: &str) -> Self {
+    pub fn new(id: &str, name: &str) -> Self {
         Self {
+            id: id.to_string(),
             name: name.to_string(),
         }
     }
+
+    /// the login of a player with an account, `None` for guests
+    pub fn account(&self) -> Option<&str> {
+        (!self.id.starts_with("guest:")).then_some(self.id.as_str())
+    }
+}
+
+/// What a room is created with.
+#[derive(Deserialize, Default, Debug)]
+pub struct Settings {
+    #[serde(default)]
+    pub rules: Rules,
+    /// robots seated right away, leaving at least one seat for a human
+    #[serde(default)]
+    pub robots: Vec<RobotSettings>,
+    /// keep the room out of the lobby, only players with the invite code
+    /// or the password get in
+    #[serde(default)]
+    pub private: bool,
+    /// lets players in without the invite code, the room is private with it
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl Settings {
+    pub fn is_private(&self) -> bool {
+        self.private || self.password.is_some()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RobotSettings {
+    #[serde(default)]
+    pub strategy: Option<Strategy>,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// A seat as everyone at the table sees it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SeatInfo {
+    pub name: String,
+    /// `None` for a human
+    pub strategy: Option<Strategy>,
+    /// a robot keeping the seat of a human who left, a newcomer takes it
+    #[serde(default)]
+    pub stand_in: bool,
+    pub ready: bool,
+    pub hand: usize,
+    pub out: Vec<Card>,
+    pub pairing: Vec<Pairing>,
+}
+
+/// The public state of a room.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoomInfo {
+    pub id: String,
+    pub rules: Rules,
+    pub seats: Vec<SeatInfo>,
+    /// whether a hand is being played
+    pub playing: bool,
+    pub turn: u8,
+    pub mode: Mode,
+    pub wall: usize,
+    pub jing: Option<Card>,
+    /// seats kept for matched players who did not connect yet
+    #[serde(default)]
+    pub reserved: usize,
+}
+
+/// A room just created, with the token its creator closes it with.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Created {
+    #[serde(flatten)]
+    pub info: RoomInfo,
+    pub token: String,
+    /// what players bring to get into a private room
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invite: Option<String>,
+}
+
+impl RoomInfo {
+    /// seats nobody sits in, stand-ins give theirs up
+    pub fn free_seats(&self) -> usize {
+        3 - self.seats.len() + self.seats.iter().filter(|s| s.stand_in).count()
+    }
+
+    /// whether someone can still sit down
+    pub fn is_open(&self) -> bool {
+        !self.playing && self.free_seats() > self.reserved
+    }
 }
 
 impl Room {
-    pub fn new() -> Self {
-        Self {
+    /// a short id that is easy to share
+    pub fn new_id() -> String {
+        random_string(6)
+    }
+
+    /// A room set up with `settings`, whose table starts on the runtime.
+    pub fn new(id: &str, state: &GlobalState, settings: &Settings) -> Result<Self> {
+        let game = Game::new(
+            id,
+            settings,
+            state.replays.clone(),
+            state.stats.clone(),
+            state.thinking,
+        )?;
+        Ok(Self {
             users: Default::default(),
-            game: Default::default(),
+            game: Arc::new(game),
+            token: None,
+            reservations: Default::default(),
+            seating: Default::default(),
+            access: None,
+        })
+    }
+
+    /// Hide the room from the lobby and return the invite code letting
+    /// players in, as `password` does too.
+    pub fn make_private(&mut self, password: Option<String>) -> String {
+        let invite = random_string(8);
+        self.access = Some(Access {
+            invite: invite.clone(),
+            password: password.filter(|p| !p.is_empty()),
+        });
+        invite
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.access.is_some()
+    }
+
+    /// whether someone bringing `invite` or `password` gets in
+    pub fn admits(&self, invite: Option<&str>, password: Option<&str>) -> bool {
+        let Some(access) = &self.access else {
+            return true;
+        };
+        invite.is_some_and(|invite| same_secret(invite, &access.invite))
+            || password
+                .zip(access.password.as_deref())
+                .is_some_and(|(password, expected)| same_secret(password, expected))
+    }
+
+    pub fn info(&self) -> RoomInfo {
+        let mut info = self.game.info();
+        info.reserved = self.reservations.lock().tokens.len();
+        info
+    }
+
+    /// Keep `seats` seats for `ttl` and return the tokens taking them.
+    pub fn reserve(&self, seats: usize, ttl: Duration) -> Vec<String> {
+        let mut reservations = self.reservations.lock();
+        let tokens: Vec<_> = (0..seats).map(|_| random_string(16)).collect();
+        reservations.tokens.extend(tokens.iter().cloned());
+        reservations.until = Some(Instant::now() + ttl);
+        tokens
+    }
+
+    /// Give up the reservations past their time, and tell whether the room
+    /// was left by the players it was reserved for.
+    pub fn expire(&self, now: Instant) -> bool {
+        let mut reservations = self.reservations.lock();
+        if reservations.until.is_none_or(|until| now < until) {
+            return false;
+        }
+        reservations.tokens.clear();
+        reservations.until = None;
+        self.users.is_empty()
+    }
+
+    /// Give the room a fresh token for its creator and return it.
+    pub fn issue_token(&mut self) -> String {
+        let token = random_string(32);
+        self.token = Some(token.clone());
+        token
+    }
+
+    /// whether `token` is the one of the creator
+    pub fn is_creator(&self, token: &str) -> bool {
+        self.token.as_deref() == Some(token)
+    }
+
+    const HELLO_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Seat `user`, unless they already sit at the table or the seats left
+    /// are reserved for others.
+    pub async fn add_user(&self, user: User, reservation: Option<&str>) -> Result<(u8, Inbox)> {
+        let _seating = self.seating.lock().await;
+        if self.seat_of(&user.id).is_some() {
+            bail!("{} is already seated", user.id);
+        }
+        let (token, keep) = {
+            let mut reservations = self.reservations.lock();
+            let tokens = &mut reservations.tokens;
+            let token = tokens
+                .iter()
+                .position(|t| Some(t.as_str()) == reservation)
+                .map(|i| tokens.remove(i));
+            (token, tokens.len())
+        };
+        // the table checks the free seats against the reservations left, it
+        // knows them as they are now
+        match self.game.join(&user.name, user.account(), keep).await {
+            Ok((seat, rx)) => {
+                self.users.insert(user, seat);
+                Ok((seat, rx))
+            }
+            Err(e) => {
+                let mut reservations = self.reservations.lock();
+                // unless the reservations ran out meanwhile
+                if let (Some(token), Some(_)) = (token, reservations.until) {
+                    reservations.tokens.push(token);
+                }
+                Err(e)
+            }
         }
     }
 
-    pub fn add_user(&mut self, user: User) {
-        self.users.insert(user);
+    pub fn seat_of(&self, user_id: &str) -> Option<u8> {
+        self.users
+            .iter()
+            .find(|e| e.key().id == user_id)
+            .map(|e| *e.value())
     }
+
+    /// the humans at the table by seat
+    pub fn users(&self) -> Vec<(u8, User)> {
+        let mut users: Vec<_> = self
+            .users
+            .iter()
+            .map(|e| (*e.value(), e.key().clone()))
+            .collect();
+        users.sort_by_key(|(seat, _)| *seat);
+        users
+    }
+
+    /// Seat whoever connects once they said hello, or let them watch, until
+    /// they hang up.
+    ///
+    /// The `account` checked before the upgrade decides who they are, the
+    /// hello only for guests.
+    pub async fn on_connection(&self, mut socket: WebSocket, account: Option<Profile>) {
+        let mut greeting = match tokio::time::timeout(Self::HELLO_TIMEOUT, hello(&mut socket)).await
+        {
+            Ok(Ok(greeting)) => greeting,
+            Ok(Err(e)) => {
+                warn!("handshake failed: {e:#}");
+                return;
+            }
+            Err(_) => {
+                warn!("no hello within {:?}", Self::HELLO_TIMEOUT);
+                return;
+            }
+        };
+        if let Some(profile) = account {
+            greeting.user = User::new(&profile.user, &profile.name);
+        }
+        let Greeting {
+            user,
+            reservation,
+            spectate,
+        } = greeting;
+        if spectate {
+            self.game.spectate(&user.name, socket).await;
+            return;
+        }
+        let (seat, rx) = match self.add_user(user.clone(), reservation.as_deref()).await {
+            Ok(joined) => joined,
+            Err(e) => {
+                warn!("{e:#}");
+                return;
+            }
+        };
+        self.game.serve(seat, rx, socket).await;
+        // a stand-in keeps the seat
+        self.users.remove(&user);
+    }
+}
+
+/// What a connection opened with.
+struct Greeting {
+    user: User,
+    reservation: Option<String>,
+    spectate: bool,
+}
+
+/// Wait for the `Hello` opening a connection.
+async fn hello(socket: &mut WebSocket) -> Result<Greeting> {
+    while let Some(message) = socket.next().await {
+        let message = message?;
+        let Ok(text) = message.to_str() else {
+            continue;
+        };
+        let message: ClientMessage =
+            serde_json::from_str(text).context("failed to deserialize client message")?;
+        let ClientMessage::Hello {
+            name,
+            user,
+            reservation,
+            spectate,
+        } = message
+        else {
+            bail!("expected Hello, got {message:?}");
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            bail!("empty name");
+        }
+        let id = format!("guest:{}", user.as_deref().unwrap_or(name));
+        return Ok(Greeting {
+            user: User::new(&id, name),
+            reservation,
+            spectate,
+        });
+    }
+    bail!("hung up before saying hello")
+}
+
+pub(crate) fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// Compare a secret someone sent with the expected one in a time that
+/// doesn't tell how much of it matched.
+fn same_secret(sent: &str, expected: &str) -> bool {
+    sent.len() == expected.len()
+        && sent
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
 }
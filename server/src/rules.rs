@@ -0,0 +1,106 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    card::{Card, Pairing},
+    eval::MIN_HU_SCORE,
+};
+
+/// The rule variations a game can be played with.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Rules {
+    /// the lowest score a hand needs to hu
+    pub min_hu_score: u8,
+    /// whether a discard can be claimed with a Ding or a Pao
+    pub claims: bool,
+}
+
+impl Rules {
+    pub const STANDARD: Rules = Rules {
+        min_hu_score: MIN_HU_SCORE,
+        claims: true,
+    };
+    /// hands end sooner with a lower score to reach
+    pub const CASUAL: Rules = Rules {
+        min_hu_score: 8,
+        claims: true,
+    };
+    /// everyone only plays from the wall
+    pub const NO_CLAIMS: Rules = Rules {
+        min_hu_score: MIN_HU_SCORE,
+        claims: false,
+    };
+
+    /// The claim offered when `seat` discards `card`, with the seat taking
+    /// it. `count` tells how many cards of the kind a seat holds. A Pao
+    /// comes before a Ding, and the next seat before the previous one.
+    pub fn claim(
+        &self,
+        seat: u8,
+        card: Card,
+        count: impl Fn(u8) -> usize,
+    ) -> Option<(u8, Pairing)> {
+        if !self.claims {
+            return None;
+        }
+        let next = next_seat(seat);
+        let prev = next_seat(next);
+        [
+            (next, 3, Pairing::Quadlet(card)),
+            (prev, 3, Pairing::Quadlet(card)),
+            (next, 2, Pairing::Triplet(card)),
+            (prev, 2, Pairing::Triplet(card)),
+        ]
+        .into_iter()
+        .find(|&(s, n, _)| count(s) == n)
+        .map(|(s, _, pairing)| (s, pairing))
+    }
+}
+
+/// The seat drawing after `seat` discards, also when a claim on the discard
+/// is declined.
+pub fn next_seat(seat: u8) -> u8 {
+    (seat + 1) % 3
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules::STANDARD
+    }
+}
+
+impl FromStr for Rules {
+    type Err = anyhow::Error;
+
+    /// `standard`, `casual` or `no-claims`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "standard" => Ok(Rules::STANDARD),
+            "casual" => Ok(Rules::CASUAL),
+            "no-claims" => Ok(Rules::NO_CLAIMS),
+            _ => bail!("unknown rule preset {s}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claim_order() {
+        let card = Card(8);
+        // seat 1 plays after seat 0 and may Ding, seat 2 may Pao
+        let counts = [0, 2, 3];
+        let claim = Rules::STANDARD.claim(0, card, |s| counts[s as usize]);
+        assert_eq!(claim, Some((2, Pairing::Quadlet(card))));
+        let claim = Rules::STANDARD.claim(2, card, |s| counts[s as usize]);
+        assert_eq!(claim, Some((1, Pairing::Triplet(card))));
+        assert_eq!(
+            Rules::NO_CLAIMS.claim(0, card, |s| counts[s as usize]),
+            None
+        );
+    }
+}
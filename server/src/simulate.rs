@@ -0,0 +1,402 @@
+use std::{fmt, ops::Range, sync::Arc};
+
+use anyhow::Result;
+use log::info;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    agent::{Difficulty, Strategy},
+    game::GameState,
+    record::{Recorder, Sample},
+    rules::Rules,
+    weights::Weights,
+};
+
+/// z value of a 95% confidence interval
+const Z: f64 = 1.96;
+
+/// Robots playing each other without any connection.
+///
+/// The line-up rotates one seat every game, so after a multiple of three
+/// games every strategy has played every seat equally often.
+pub struct Simulation {
+    seats: [Strategy; 3],
+    /// the weights of each strategy, the configured ones when `None`
+    weights: Option<[Weights; 3]>,
+    recorder: Option<Arc<Recorder>>,
+    rules: Rules,
+    seed: u64,
+}
+
+/// How one game ended, seats being positions in the line-up.
+struct Outcome {
+    winner: Option<usize>,
+    turns: usize,
+    score: Option<u8>,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Report {
+    pub seats: [Strategy; 3],
+    pub rules: Rules,
+    pub seed: u64,
+    pub games: usize,
+    /// games won by each strategy of the line-up, whichever seat it had
+    pub wins: [usize; 3],
+    pub draws: usize,
+    /// discards over all games
+    pub turns: usize,
+    /// the winning scores added up
+    pub winning_score: usize,
+}
+
+impl Simulation {
+    pub fn new(seats: [Strategy; 3], rules: Rules, seed: u64) -> Self {
+        Self {
+            seats,
+            weights: None,
+            recorder: None,
+            rules,
+            seed,
+        }
+    }
+
+    pub fn with_weights(mut self, weights: [Weights; 3]) -> Self {
+        self.weights = Some(weights);
+        self
+    }
+
+    /// Record every decision of the robots.
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// the table of `rotation`, seat `i` playing `seats[(i + rotation) % 3]`
+    fn table(&self, rotation: usize) -> GameState {
+        let mut game = GameState::default();
+        game.set_rules(self.rules);
+        for i in 0..3 {
+            let strategy = self.seats[(i + rotation) % 3].clone();
+            game.add_robot(Some(strategy), Difficulty::Hard, None)
+                .expect("a new table has free seats");
+            if let Some(weights) = self.weights {
+                game.players[i].weights = weights[(i + rotation) % 3];
+            }
+            game.players[i].record = self.recorder.is_some();
+        }
+        game
+    }
+
+    /// Play `games` games sharded over `threads` worker threads. Every game
+    /// has its own seed, so the report does not depend on `threads`.
+    pub fn run(&self, games: usize, threads: usize) -> Result<Report> {
+        let threads = threads.clamp(1, games.max(1));
+        let shard = games.div_ceil(threads);
+        let reports = std::thread::scope(|s| {
+            let workers: Vec<_> = (0..threads)
+                .map(|i| {
+                    let games = i * shard..((i + 1) * shard).min(games);
+                    s.spawn(move || self.run_shard(games))
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| w.join().expect("simulation worker panicked"))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        let mut report = Report::new(self.seats.clone(), self.rules, self.seed);
+        for r in &reports {
+            report.merge(r);
+        }
+        Ok(report)
+    }
+
+    fn run_shard(&self, games: Range<usize>) -> Result<Report> {
+        // keep a table per rotation so that external bots start only once
+        let mut tables: Vec<GameState> = (0..3).map(|r| self.table(r)).collect();
+        let mut report = Report::new(self.seats.clone(), self.rules, self.seed);
+        for n in games {
+            let rotation = n % 3;
+            let game = &mut tables[rotation];
+            let seed = sub_seed(self.seed, n);
+            game.seed(seed);
+            let outcome = play(game)?;
+            if let Some(recorder) = &self.recorder {
+                recorder.write(&self.samples(game, rotation, seed, &outcome))?;
+            }
+            report.record(Outcome {
+                winner: outcome.winner.map(|seat| (seat + rotation) % 3),
+                ..outcome
+            });
+        }
+        Ok(report)
+    }
+
+    /// take the decisions recorded in the game just played
+    fn samples(
+        &self,
+        game: &mut GameState,
+        rotation: usize,
+        seed: u64,
+        outcome: &Outcome,
+    ) -> Vec<Sample> {
+        let seats: [Strategy; 3] = std::array::from_fn(|i| self.seats[(i + rotation) % 3].clone());
+        let mut samples = vec![];
+        for (seat, player) in game.players.iter_mut().enumerate() {
+            for decision in std::mem::take(&mut player.decisions) {
+                samples.push(Sample {
+                    seed,
+                    seats: seats.clone(),
+                    rules: self.rules,
+                    seat: seat as u8,
+                    weights: player.weights,
+                    difficulty: player.difficulty,
+                    decision,
+                    winner: outcome.winner.map(|w| w as u8),
+                    winning_score: outcome.score,
+                });
+            }
+        }
+        samples
+    }
+}
+
+/// the seed of the `n`th game or match, independent of the ones before it
+pub(crate) fn sub_seed(seed: u64, n: usize) -> u64 {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    bytes[8..16].copy_from_slice(&(n as u64).to_le_bytes());
+    StdRng::from_seed(bytes).gen()
+}
+
+fn play(game: &mut GameState) -> Result<Outcome> {
+    info!("=========================== new game =========================");
+    game.start()?;
+    let mut turns = 0;
+    while !game.is_over() {
+        if let Some(card) = game.robot_turn(None) {
+            game.next_turn(&card);
+            turns += 1;
+        }
+    }
+    let winner = game.winner.filter(|&w| w != u8::MAX);
+    Ok(Outcome {
+        winner: winner.map(|w| w as usize),
+        turns,
+        score: game.winning_score,
+    })
+}
+
+impl Report {
+    fn new(seats: [Strategy; 3], rules: Rules, seed: u64) -> Self {
+        Self {
+            seats,
+            rules,
+            seed,
+            games: 0,
+            wins: [0; 3],
+            draws: 0,
+            turns: 0,
+            winning_score: 0,
+        }
+    }
+
+    fn record(&mut self, outcome: Outcome) {
+        self.games += 1;
+        self.turns += outcome.turns;
+        match outcome.winner {
+            Some(w) => {
+                self.wins[w] += 1;
+                self.winning_score += outcome.score.unwrap_or(0) as usize;
+            }
+            None => self.draws += 1,
+        }
+    }
+
+    /// add the games of `other`, played with the same line-up
+    fn merge(&mut self, other: &Report) {
+        self.games += other.games;
+        for i in 0..3 {
+            self.wins[i] += other.wins[i];
+        }
+        self.draws += other.draws;
+        self.turns += other.turns;
+        self.winning_score += other.winning_score;
+    }
+
+    fn rate(&self, n: usize) -> f64 {
+        if self.games == 0 {
+            return 0.0;
+        }
+        n as f64 / self.games as f64
+    }
+
+    pub fn win_rate(&self, seat: usize) -> f64 {
+        self.rate(self.wins[seat])
+    }
+
+    /// Wilson score interval of the win rate of `seat`.
+    pub fn win_interval(&self, seat: usize) -> (f64, f64) {
+        wilson(self.wins[seat], self.games)
+    }
+
+    pub fn draw_rate(&self) -> f64 {
+        self.rate(self.draws)
+    }
+
+    /// discards per game
+    pub fn average_turns(&self) -> f64 {
+        self.rate(self.turns)
+    }
+
+    pub fn average_winning_score(&self) -> f64 {
+        let won = self.games - self.draws;
+        if won == 0 {
+            return 0.0;
+        }
+        self.winning_score as f64 / won as f64
+    }
+}
+
+fn wilson(successes: usize, n: usize) -> (f64, f64) {
+    if n == 0 {
+        return (0.0, 1.0);
+    }
+    let n = n as f64;
+    let p = successes as f64 / n;
+    let z2 = Z * Z;
+    let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let margin = Z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+    ((center - margin).max(0.0), (center + margin).min(1.0))
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} games, seed {}, {:?}",
+            self.games, self.seed, self.rules
+        )?;
+        for (i, strategy) in self.seats.iter().enumerate() {
+            let (low, high) = self.win_interval(i);
+            writeln!(
+                f,
+                "{i} {:<12} wins {:>6}  {:5.1}% [{:5.1}%, {:5.1}%]",
+                strategy.to_string(),
+                self.wins[i],
+                self.win_rate(i) * 100.0,
+                low * 100.0,
+                high * 100.0
+            )?;
+        }
+        writeln!(f, "draws {:.1}%", self.draw_rate() * 100.0)?;
+        writeln!(f, "average hand length {:.1} turns", self.average_turns())?;
+        write!(
+            f,
+            "average winning score {:.1}",
+            self.average_winning_score()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_report() {
+        let seats = [Strategy::Level1, Strategy::Random, Strategy::Random];
+        let simulation = Simulation::new(seats, Rules::default(), 7);
+        let report = simulation.run(6, 1).unwrap();
+        assert_eq!(report.games, 6);
+        assert_eq!(report.wins.iter().sum::<usize>() + report.draws, 6);
+        assert_eq!(report, simulation.run(6, 1).unwrap());
+    }
+
+    #[test]
+    fn test_report_ignores_thread_count() {
+        let seats = [Strategy::Level1, Strategy::Level1, Strategy::Random];
+        let simulation = Simulation::new(seats, Rules::default(), 11);
+        let report = simulation.run(10, 1).unwrap();
+        assert_eq!(report, simulation.run(10, 3).unwrap());
+        assert_eq!(report, simulation.run(10, 16).unwrap());
+    }
+
+    /// the samples a recorder wrote to `path`
+    fn read_samples(path: &str) -> Vec<Sample> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_recorded_game_can_be_replayed() {
+        let dir = std::env::temp_dir().join(format!("shangdaren-samples-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("samples.jsonl");
+        let path = path.to_str().unwrap();
+        let recorder = Arc::new(Recorder::create(path).unwrap());
+        let seats = [Strategy::Level1, Strategy::Random, Strategy::Level1];
+        let tuned = Weights {
+            danger: 2.0,
+            ..Weights::default()
+        };
+        Simulation::new(seats, Rules::default(), 5)
+            .with_weights([tuned, Weights::default(), Weights::default()])
+            .with_recorder(recorder.clone())
+            .run(2, 1)
+            .unwrap();
+        recorder.flush().unwrap();
+        let samples = read_samples(path);
+        assert!(!samples.is_empty());
+        for s in &samples {
+            assert!(s.decision.legal.contains(&s.decision.chosen));
+            assert_eq!(s.difficulty, Difficulty::Hard);
+            // the tuned weights go with the first strategy of the line-up,
+            // which moved one seat for the second game
+            let rotation = if s.seed == samples[0].seed { 0 } else { 1 };
+            assert_eq!(s.weights == tuned, s.seat as usize == (3 - rotation) % 3);
+        }
+
+        // the seed of the first game deals it again
+        let first = &samples[0];
+        let mut replay = Simulation::new(first.seats.clone(), first.rules, 0).with_weights([
+            tuned,
+            Weights::default(),
+            Weights::default(),
+        ]);
+        let again_path = dir.join("again.jsonl");
+        let again_path = again_path.to_str().unwrap();
+        let again_recorder = Arc::new(Recorder::create(again_path).unwrap());
+        replay.recorder = Some(again_recorder.clone());
+        let mut game = replay.table(0);
+        game.seed(first.seed);
+        let outcome = play(&mut game).unwrap();
+        again_recorder
+            .write(&replay.samples(&mut game, 0, first.seed, &outcome))
+            .unwrap();
+        again_recorder.flush().unwrap();
+        let again = read_samples(again_path);
+        std::fs::remove_dir_all(&dir).ok();
+        let recorded: Vec<_> = samples.iter().filter(|s| s.seed == first.seed).collect();
+        assert_eq!(again.len(), recorded.len());
+        for (a, b) in again.iter().zip(recorded) {
+            assert_eq!(a.seat, b.seat);
+            assert_eq!(a.weights, b.weights);
+            assert_eq!(a.difficulty, b.difficulty);
+            assert_eq!(a.decision.chosen, b.decision.chosen);
+        }
+    }
+
+    #[test]
+    fn test_wilson() {
+        let (low, high) = wilson(50, 100);
+        assert!(low < 0.5 && 0.5 < high);
+        assert!((low - 0.4038).abs() < 1e-3);
+        assert_eq!(wilson(0, 10).0, 0.0);
+    }
+}
@@ -0,0 +1,255 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::accounts::{load_json, save_json};
+
+/// How a seat did in a finished hand.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SeatResult {
+    /// the player with an account at the seat, robots and guests have none
+    pub account: Option<String>,
+    pub won: bool,
+    /// won on a card drawn in their own turn, not the one after a Pao
+    pub self_draw: bool,
+    /// the score of the winning hand
+    pub score: Option<u8>,
+    pub dings: u32,
+    pub paos: u32,
+    /// the jing cards in the winning hand
+    pub jing: u32,
+}
+
+/// What the hands of a player add up to.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PlayerStats {
+    pub hands: u32,
+    pub wins: u32,
+    pub self_draws: u32,
+    /// the scores of all wins added up
+    pub total_score: u64,
+    pub dings: u32,
+    pub paos: u32,
+    pub jing: u32,
+    pub rating: f64,
+}
+
+impl Default for PlayerStats {
+    fn default() -> Self {
+        Self {
+            hands: 0,
+            wins: 0,
+            self_draws: 0,
+            total_score: 0,
+            dings: 0,
+            paos: 0,
+            jing: 0,
+            rating: Stats::RATING,
+        }
+    }
+}
+
+impl PlayerStats {
+    pub fn average_score(&self) -> Option<f64> {
+        (self.wins > 0).then(|| self.total_score as f64 / self.wins as f64)
+    }
+}
+
+/// A line of the leaderboard.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Standing {
+    pub user: String,
+    pub average_score: Option<f64>,
+    #[serde(flatten)]
+    pub stats: PlayerStats,
+}
+
+/// The stats of the players with an account, kept in a JSON file written
+/// after every hand, or only in memory.
+pub struct Stats {
+    path: Option<PathBuf>,
+    players: Mutex<HashMap<String, PlayerStats>>,
+}
+
+impl Stats {
+    /// the rating of newcomers
+    pub const RATING: f64 = 1500.0;
+    /// how far a hand moves the rating of a seat against each other seat
+    const K: f64 = 16.0;
+
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            players: Default::default(),
+        }
+    }
+
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let players = load_json(&path)?;
+        Ok(Self {
+            path: Some(path),
+            players: Mutex::new(players),
+        })
+    }
+
+    /// Count a finished hand, rating each seat against the other two: a
+    /// win beats them, a hand without a winner ties them. Only hands where
+    /// every seat has an account are rated, robots and guests could be
+    /// picked weak on purpose.
+    pub fn record(&self, seats: &[SeatResult]) -> Result<()> {
+        if seats.iter().all(|s| s.account.is_none()) {
+            return Ok(());
+        }
+        let rated = seats.iter().all(|s| s.account.is_some());
+        let mut players = self.players.lock();
+        let ratings: Vec<f64> = seats
+            .iter()
+            .map(|s| match &s.account {
+                Some(user) => players.get(user).map_or(Self::RATING, |p| p.rating),
+                None => Self::RATING,
+            })
+            .collect();
+        let nobody_won = seats.iter().all(|s| !s.won);
+        for (i, seat) in seats.iter().enumerate() {
+            let Some(user) = &seat.account else {
+                continue;
+            };
+            let mut change = 0.0;
+            for (j, other) in seats.iter().enumerate() {
+                if i == j || !rated {
+                    continue;
+                }
+                let expected = 1.0 / (1.0 + 10f64.powf((ratings[j] - ratings[i]) / 400.0));
+                let actual = match (seat.won, other.won) {
+                    (true, _) => 1.0,
+                    (_, true) => 0.0,
+                    _ if nobody_won => 0.5,
+                    // the two losers of a hand don't rate each other
+                    _ => continue,
+                };
+                change += Self::K * (actual - expected);
+            }
+            let stats = players.entry(user.clone()).or_default();
+            stats.hands += 1;
+            stats.wins += seat.won as u32;
+            stats.self_draws += seat.self_draw as u32;
+            stats.total_score += seat.score.unwrap_or(0) as u64;
+            stats.dings += seat.dings;
+            stats.paos += seat.paos;
+            stats.jing += seat.jing;
+            stats.rating += change;
+        }
+        match &self.path {
+            Some(path) => save_json(path, &*players),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get(&self, user: &str) -> Option<PlayerStats> {
+        self.players.lock().get(user).cloned()
+    }
+
+    /// the `limit` best rated players, the best first
+    pub fn leaderboard(&self, limit: usize) -> Vec<Standing> {
+        let mut standings: Vec<_> = self
+            .players
+            .lock()
+            .iter()
+            .map(|(user, stats)| Standing {
+                user: user.clone(),
+                average_score: stats.average_score(),
+                stats: stats.clone(),
+            })
+            .collect();
+        standings.sort_by(|a, b| {
+            b.stats
+                .rating
+                .total_cmp(&a.stats.rating)
+                .then(a.user.cmp(&b.user))
+        });
+        standings.truncate(limit);
+        standings
+    }
+}
+
+/// The file named by `SHANGDAREN_STATS`, or memory when it is not set.
+pub fn configured() -> Result<Arc<Stats>> {
+    Ok(Arc::new(match std::env::var_os("SHANGDAREN_STATS") {
+        Some(path) => Stats::open(path)?,
+        None => Stats::in_memory(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seat(account: Option<&str>) -> SeatResult {
+        SeatResult {
+            account: account.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let stats = Stats::in_memory();
+        let winner = SeatResult {
+            won: true,
+            self_draw: true,
+            score: Some(30),
+            jing: 2,
+            paos: 1,
+            ..seat(Some("alice"))
+        };
+        stats
+            .record(&[winner.clone(), seat(Some("bob")), seat(Some("carol"))])
+            .unwrap();
+        let alice = stats.get("alice").unwrap();
+        assert_eq!((alice.hands, alice.wins, alice.self_draws), (1, 1, 1));
+        assert_eq!(alice.average_score(), Some(30.0));
+        assert_eq!((alice.paos, alice.jing), (1, 2));
+        let bob = stats.get("bob").unwrap();
+        assert_eq!((bob.hands, bob.wins), (1, 0));
+        assert!(alice.rating > Stats::RATING && bob.rating < Stats::RATING);
+
+        // a hand without a winner pulls the ratings together
+        stats
+            .record(&[seat(Some("alice")), seat(Some("bob")), seat(Some("carol"))])
+            .unwrap();
+        assert!(stats.get("alice").unwrap().rating < alice.rating);
+        assert!(stats.get("bob").unwrap().rating > bob.rating);
+
+        let board = stats.leaderboard(10);
+        assert_eq!(
+            board.iter().map(|s| s.user.as_str()).collect::<Vec<_>>(),
+            ["alice", "bob", "carol"]
+        );
+        assert_eq!(stats.leaderboard(1).len(), 1);
+        // robots and guests have no stats
+        stats.record(&[seat(None), seat(None), seat(None)]).unwrap();
+        assert_eq!(stats.leaderboard(10).len(), 3);
+    }
+
+    #[test]
+    fn test_robots_dont_rate() {
+        let stats = Stats::in_memory();
+        let winner = SeatResult {
+            won: true,
+            ..seat(Some("alice"))
+        };
+        // beating a robot picked to lose counts the hand but not the rating
+        for _ in 0..10 {
+            stats
+                .record(&[winner.clone(), seat(Some("bob")), seat(None)])
+                .unwrap();
+        }
+        let alice = stats.get("alice").unwrap();
+        assert_eq!((alice.hands, alice.wins), (10, 10));
+        assert_eq!(alice.rating, Stats::RATING);
+        assert_eq!(stats.get("bob").unwrap().rating, Stats::RATING);
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use log::info;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{agent::Strategy, rules::Rules, simulate::Simulation, weights::Weights};
+
+/// Random search over the `Level1` weights: a robot playing a candidate
+/// faces the opponents and the candidate replaces the best one when it wins
+/// more often.
+///
+/// Every candidate plays the same deals, so that the comparison is not
+/// drowned by the luck of the draw. The pick is then scored again on deals
+/// the search never saw, since it is the luckiest one on the search deals.
+pub struct Tuner {
+    opponents: [Strategy; 2],
+    rules: Rules,
+    seed: u64,
+    /// games per candidate
+    games: usize,
+    threads: usize,
+}
+
+impl Tuner {
+    pub fn new(
+        opponents: [Strategy; 2],
+        rules: Rules,
+        seed: u64,
+        games: usize,
+        threads: usize,
+    ) -> Self {
+        Self {
+            opponents,
+            rules,
+            seed,
+            games,
+            threads,
+        }
+    }
+
+    /// the win rate of a `Level1` robot playing `weights` on the deals of `seed`
+    fn score(&self, weights: Weights, seed: u64) -> Result<f64> {
+        let [right, left] = self.opponents.clone();
+        let report = Simulation::new([Strategy::Level1, right, left], self.rules, seed)
+            .with_weights([weights, Weights::default(), Weights::default()])
+            .run(self.games, self.threads)?;
+        Ok(report.win_rate(0))
+    }
+
+    /// Search from the default weights for `iterations` candidates and
+    /// return the best weights, scored on held-out deals too.
+    pub fn run(&self, iterations: usize) -> Result<Tuned> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let held_out = rng.gen();
+        let mut best = Weights::default();
+        let mut best_rate = self.score(best, self.seed)?;
+        info!("default weights win {:.2}%", best_rate * 100.0);
+        for i in 0..iterations {
+            let candidate = best.perturb(&mut rng);
+            let rate = self.score(candidate, self.seed)?;
+            info!("candidate {i} wins {:.2}%: {candidate:?}", rate * 100.0);
+            if rate > best_rate {
+                best = candidate;
+                best_rate = rate;
+            }
+        }
+        Ok(Tuned {
+            weights: best,
+            search_rate: best_rate,
+            held_out_rate: self.score(best, held_out)?,
+            default_rate: self.score(Weights::default(), held_out)?,
+        })
+    }
+}
+
+/// The weights a search picked.
+pub struct Tuned {
+    pub weights: Weights,
+    /// the win rate on the search deals, flattering since it is the best one
+    pub search_rate: f64,
+    /// the win rate on deals the search never saw
+    pub held_out_rate: f64,
+    /// the win rate of the default weights on the same held-out deals
+    pub default_rate: f64,
+}
+
+impl Tuned {
+    /// whether the weights still beat the default ones on held-out deals
+    pub fn is_better(&self) -> bool {
+        self.held_out_rate > self.default_rate
+    }
+}
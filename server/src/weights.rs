@@ -0,0 +1,145 @@
+use std::sync::OnceLock;
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// The knobs of the `Level1` heuristic, see `Agent::form_shun` and
+/// `Agent::form_ke`.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct Weights {
+    /// how much more the 上大人 and the jing categories are worth
+    pub valuable_category: f32,
+    /// cards the neighbours hold that a Ding can come from
+    pub claim_pool: f32,
+    /// draws looked ahead for the first missing card
+    pub near_draws: usize,
+    /// draws looked ahead for the second missing card
+    pub far_draws: usize,
+    /// scores closer than `1 / quantization` count as equal
+    pub quantization: f32,
+    /// how much the risk of feeding a claim weighs against keeping a card
+    pub danger: f32,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            valuable_category: 2.0,
+            claim_pool: 38.0,
+            near_draws: 3,
+            far_draws: 6,
+            quantization: 1000.0,
+            danger: 0.5,
+        }
+    }
+}
+
+/// look-ahead deeper than this gets slow, `get_prob_of` walks `2^draws` cases
+const MAX_DRAWS: usize = 10;
+
+impl Weights {
+    pub fn load(path: &str) -> Result<Self> {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+        let weights: Self =
+            serde_json::from_str(&content).with_context(|| format!("failed to parse {path}"))?;
+        weights
+            .checked()
+            .with_context(|| format!("bad weights in {path}"))
+    }
+
+    /// Refuse weights the heuristic can't use and bring the look-ahead
+    /// within `MAX_DRAWS`.
+    fn checked(self) -> Result<Self> {
+        if !(self.quantization.is_finite() && self.quantization > 0.0) {
+            bail!("quantization must be positive, got {}", self.quantization);
+        }
+        let floats = [self.valuable_category, self.claim_pool, self.danger];
+        if floats.iter().any(|x| !x.is_finite()) {
+            bail!("weights must be finite numbers");
+        }
+        let near_draws = self.near_draws.clamp(1, MAX_DRAWS);
+        Ok(Self {
+            near_draws,
+            far_draws: self.far_draws.clamp(near_draws, MAX_DRAWS),
+            ..self
+        })
+    }
+
+    /// The weights in the file named by `SHANGDAREN_WEIGHTS`, or the default
+    /// ones when it is not set or cannot be loaded. The file is read once.
+    pub fn configured() -> Self {
+        static CONFIGURED: OnceLock<Weights> = OnceLock::new();
+        *CONFIGURED.get_or_init(|| {
+            let Ok(path) = std::env::var("SHANGDAREN_WEIGHTS") else {
+                return Self::default();
+            };
+            Self::load(&path).unwrap_or_else(|e| {
+                warn!("using the default weights: {e:#}");
+                Self::default()
+            })
+        })
+    }
+
+    /// A neighbour of `self` for random search.
+    pub fn perturb(&self, rng: &mut impl Rng) -> Self {
+        let mut scale = |x: f32| x * rng.gen_range(0.7..1.3);
+        let mut weights = Self {
+            valuable_category: scale(self.valuable_category),
+            claim_pool: scale(self.claim_pool),
+            quantization: scale(self.quantization),
+            danger: scale(self.danger),
+            ..*self
+        };
+        let mut step = |x: usize| (x as i64 + rng.gen_range(-1..=1)).clamp(1, MAX_DRAWS as i64);
+        weights.near_draws = step(self.near_draws) as usize;
+        weights.far_draws = (step(self.far_draws) as usize).max(weights.near_draws);
+        weights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn test_perturb_keeps_draws_in_range() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut weights = Weights::default();
+        for _ in 0..100 {
+            weights = weights.perturb(&mut rng);
+            assert!((1..=MAX_DRAWS).contains(&weights.near_draws));
+            assert!(weights.near_draws <= weights.far_draws);
+            assert!(weights.far_draws <= MAX_DRAWS);
+        }
+    }
+
+    #[test]
+    fn test_load_checks_the_weights() {
+        let path =
+            std::env::temp_dir().join(format!("shangdaren-weights-{}.json", std::process::id()));
+        let load = |json: &str| {
+            std::fs::write(&path, json).unwrap();
+            Weights::load(path.to_str().unwrap())
+        };
+        let weights = load(r#"{"near_draws": 0, "far_draws": 64}"#).unwrap();
+        assert_eq!((weights.near_draws, weights.far_draws), (1, MAX_DRAWS));
+        let weights = load(r#"{"near_draws": 5, "far_draws": 2}"#).unwrap();
+        assert_eq!((weights.near_draws, weights.far_draws), (5, 5));
+        assert!(load(r#"{"quantization": 0}"#).is_err());
+        assert!(load(r#"{"quantization": -1.0}"#).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_fields_are_default() {
+        let weights: Weights = serde_json::from_str(r#"{"danger": 1.0}"#).unwrap();
+        assert_eq!(weights.danger, 1.0);
+        assert_eq!(weights.near_draws, Weights::default().near_draws);
+    }
+}